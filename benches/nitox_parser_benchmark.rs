@@ -25,6 +25,7 @@ fn benchmark_parser(c: &mut Criterion) {
                 subject: String::new(),
                 payload: bytes::Bytes::new(),
                 reply_to: None,
+                headers: None,
             }.into_vec()
         })
     });
@@ -89,6 +90,7 @@ fn benchmark_parser(c: &mut Criterion) {
                 subject: String::new(),
                 sid: String::new(),
                 reply_to: None,
+                headers: None,
                 payload: bytes::Bytes::new(),
             }.into_vec()
         })
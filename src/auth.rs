@@ -0,0 +1,282 @@
+use base64;
+use nkeys::{self, KeyPair};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use zeroize::Zeroizing;
+
+use error::NatsError;
+use protocol::commands::ConnectCommand;
+
+/// Signs the nonce sent by the server with the private NKey of the user, returning the raw ed25519 signature
+pub type NKeySigner = dyn Fn(&[u8]) -> Result<Vec<u8>, NatsError> + Send + Sync;
+
+const JWT_BLOCK: &str = "NATS USER JWT";
+const SEED_BLOCK: &str = "USER NKEY SEED";
+
+/// Where the credentials come from
+#[derive(Clone)]
+enum NKeySource {
+    Signer {
+        /// Public NKey of the user, sent as `nkey` in the CONNECT command
+        public_key: Option<String>,
+        /// User JWT, sent as `jwt` in the CONNECT command
+        jwt: Option<String>,
+        signer: Arc<NKeySigner>,
+    },
+    /// `.creds` file holding the user JWT and the NKey seed
+    CredentialsFile(PathBuf),
+    /// File holding the NKey seed only
+    SeedFile(PathBuf),
+}
+
+/// NKey authentication, optionally along with a user JWT for decentralized (operator/account) setups.
+///
+/// The server sends a nonce in its INFO, which is signed during each handshake and sent in the CONNECT command.
+/// Credentials and seed files are read again on each handshake, and the seed is wiped from memory once used.
+#[derive(Clone)]
+pub struct NKeyAuth(NKeySource);
+
+impl NKeyAuth {
+    /// Authenticates with the given NKey seed (`SU...`), the public key is derived from it
+    pub fn from_seed(seed: &str) -> Result<Self, NatsError> {
+        let key_pair = KeyPair::from_seed(seed).map_err(|e| NatsError::NKeyError(describe(&e)))?;
+        let public_key = key_pair.public_key();
+
+        Ok(NKeyAuth(NKeySource::Signer {
+            public_key: Some(public_key),
+            jwt: None,
+            signer: Arc::new(move |nonce| key_pair.sign(nonce).map_err(|e| NatsError::NKeyError(describe(&e)))),
+        }))
+    }
+
+    /// Authenticates with a callback signing the nonce, for seeds that can't be handed to the client (HSM, vault...).
+    /// The public key can be omitted when a JWT is given, since the server gets it from the JWT
+    pub fn from_signer<F>(public_key: Option<String>, signer: F) -> Self
+    where
+        F: Fn(&[u8]) -> Result<Vec<u8>, NatsError> + Send + Sync + 'static,
+    {
+        NKeyAuth(NKeySource::Signer {
+            public_key,
+            jwt: None,
+            signer: Arc::new(signer),
+        })
+    }
+
+    /// Authenticates with a standard `.creds` file, holding the user JWT and the NKey seed
+    pub fn from_credentials_file<P: AsRef<Path>>(path: P) -> Self {
+        NKeyAuth(NKeySource::CredentialsFile(path.as_ref().to_path_buf()))
+    }
+
+    /// Authenticates with a file holding the NKey seed, either bare or within a `USER NKEY SEED` block
+    pub fn from_seed_file<P: AsRef<Path>>(path: P) -> Self {
+        NKeyAuth(NKeySource::SeedFile(path.as_ref().to_path_buf()))
+    }
+
+    /// Sends the given user JWT along with the signature, for decentralized authentication. Has no effect on
+    /// credentials files, which carry their own JWT
+    pub fn with_jwt<S: Into<String>>(mut self, jwt: S) -> Self {
+        if let NKeySource::Signer { jwt: ref mut current, .. } = self.0 {
+            *current = Some(jwt.into());
+        }
+
+        self
+    }
+
+    /// Signs the nonce of the server and puts the credentials in the CONNECT command
+    pub(crate) fn sign_connect(&self, cmd: &mut ConnectCommand, nonce: &str) -> Result<(), NatsError> {
+        let (public_key, jwt, sig) = match self.0 {
+            NKeySource::Signer {
+                ref public_key,
+                ref jwt,
+                ref signer,
+            } => (public_key.clone(), jwt.clone(), signer(nonce.as_bytes())?),
+            NKeySource::CredentialsFile(ref path) => {
+                let contents = read_file(path)?;
+                let jwt = extract_block(&contents, JWT_BLOCK).ok_or_else(|| malformed(path, "no user JWT found"))?;
+                let seed =
+                    extract_block(&contents, SEED_BLOCK).ok_or_else(|| malformed(path, "no user NKey seed found"))?;
+                let (_, sig) = sign_with_seed(path, seed, nonce)?;
+
+                (None, Some(jwt.to_string()), sig)
+            }
+            NKeySource::SeedFile(ref path) => {
+                let contents = read_file(path)?;
+                let seed = extract_block(&contents, SEED_BLOCK)
+                    .or_else(|| contents.lines().map(|l| l.trim()).find(|l| !l.is_empty()))
+                    .ok_or_else(|| malformed(path, "no NKey seed found"))?;
+                let (public_key, sig) = sign_with_seed(path, seed, nonce)?;
+
+                (Some(public_key), None, sig)
+            }
+        };
+
+        cmd.set_signature(public_key, jwt, base64::encode_config(&sig, base64::URL_SAFE_NO_PAD));
+        Ok(())
+    }
+}
+
+/// Reads a file holding secrets, which are wiped from memory once dropped
+fn read_file(path: &Path) -> Result<Zeroizing<String>, NatsError> {
+    fs::read_to_string(path)
+        .map(Zeroizing::new)
+        .map_err(|e| NatsError::CredentialsError(format!("cannot read {}: {}", path.display(), e)))
+}
+
+fn malformed(path: &Path, reason: &str) -> NatsError {
+    NatsError::CredentialsError(format!("{} is malformed: {}", path.display(), reason))
+}
+
+/// Signs the nonce with the given seed, returning the public key along with the signature
+fn sign_with_seed(path: &Path, seed: &str, nonce: &str) -> Result<(String, Vec<u8>), NatsError> {
+    let key_pair = KeyPair::from_seed(seed).map_err(|e| malformed(path, &describe(&e)))?;
+    let sig = key_pair
+        .sign(nonce.as_bytes())
+        .map_err(|e| NatsError::NKeyError(describe(&e)))?;
+
+    Ok((key_pair.public_key(), sig))
+}
+
+/// Describes an nkeys error by its kind, as formatting the error itself recurses endlessly
+fn describe(e: &nkeys::error::Error) -> String {
+    e.kind().to_string()
+}
+
+/// Content of a `-----BEGIN <name>-----` / `------END <name>------` block, which is a single line
+fn extract_block<'a>(contents: &'a str, name: &str) -> Option<&'a str> {
+    let mut lines = contents.lines().map(|l| l.trim());
+    lines.find(|l| l.starts_with("-----BEGIN") && l.contains(name))?;
+    lines
+        .find(|l| !l.is_empty())
+        .filter(|l| !l.starts_with("-----"))
+}
+
+impl fmt::Debug for NKeyAuth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            NKeySource::Signer {
+                ref public_key, ref jwt, ..
+            } => f
+                .debug_struct("NKeyAuth")
+                .field("public_key", public_key)
+                .field("jwt", &jwt.as_ref().map(|_| "<redacted>"))
+                .field("signer", &"Fn...")
+                .finish(),
+            NKeySource::CredentialsFile(ref path) => f.debug_tuple("NKeyAuth::CredentialsFile").field(path).finish(),
+            NKeySource::SeedFile(ref path) => f.debug_tuple("NKeyAuth::SeedFile").field(path).finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extract_block, NKeyAuth, JWT_BLOCK, SEED_BLOCK};
+    use base64;
+    use error::NatsError;
+    use nkeys::KeyPair;
+    use protocol::commands::ConnectCommand;
+    use std::{env, fs};
+
+    static CREDS: &str = "-----BEGIN NATS USER JWT-----
+eyJ0eXAiOiJqd3QifQ
+------END NATS USER JWT------
+
+************************* IMPORTANT *************************
+NKEY Seed printed below can be used to sign and prove identity.
+NKEYs are sensitive and should be treated as secrets.
+
+-----BEGIN USER NKEY SEED-----
+{seed}
+------END USER NKEY SEED------
+
+*************************************************************
+";
+
+    fn signature(cmd: &ConnectCommand) -> Vec<u8> {
+        let json = ::serde_json::to_value(cmd).unwrap();
+        base64::decode_config(json["sig"].as_str().unwrap(), base64::URL_SAFE_NO_PAD).unwrap()
+    }
+
+    #[test]
+    fn it_signs_the_nonce() {
+        let key_pair = KeyPair::new_user();
+        let auth = NKeyAuth::from_seed(&key_pair.seed().unwrap()).unwrap().with_jwt("eyJ0eXAiOiJqd3QifQ");
+        let mut cmd = ConnectCommand::builder().build().unwrap();
+        auth.sign_connect(&mut cmd, "nonce").unwrap();
+
+        let json = ::serde_json::to_value(&cmd).unwrap();
+        assert_eq!(json["nkey"], key_pair.public_key());
+        assert_eq!(json["jwt"], "eyJ0eXAiOiJqd3QifQ");
+        assert!(key_pair.verify(b"nonce", &signature(&cmd)).is_ok());
+    }
+
+    #[test]
+    fn it_rejects_invalid_seeds() {
+        match NKeyAuth::from_seed("SUNOTASEED") {
+            Err(NatsError::NKeyError(ref reason)) => assert!(!reason.is_empty()),
+            res => panic!("Expected an invalid seed, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn it_extracts_blocks() {
+        assert_eq!(extract_block(CREDS, JWT_BLOCK), Some("eyJ0eXAiOiJqd3QifQ"));
+        assert_eq!(extract_block(CREDS, SEED_BLOCK), Some("{seed}"));
+        assert_eq!(extract_block("-----BEGIN NATS USER JWT-----\n------END NATS USER JWT------\n", JWT_BLOCK), None);
+        assert_eq!(extract_block("eyJ0eXAiOiJqd3QifQ", JWT_BLOCK), None);
+    }
+
+    #[test]
+    fn it_reads_credentials_files() {
+        let key_pair = KeyPair::new_user();
+        let path = env::temp_dir().join(format!("nitox-{}.creds", key_pair.public_key()));
+        fs::write(&path, CREDS.replace("{seed}", &key_pair.seed().unwrap())).unwrap();
+
+        let mut cmd = ConnectCommand::builder().build().unwrap();
+        let res = NKeyAuth::from_credentials_file(&path).sign_connect(&mut cmd, "nonce");
+        let _ = fs::remove_file(&path);
+        assert!(res.is_ok());
+
+        let json = ::serde_json::to_value(&cmd).unwrap();
+        assert_eq!(json["jwt"], "eyJ0eXAiOiJqd3QifQ");
+        assert!(key_pair.verify(b"nonce", &signature(&cmd)).is_ok());
+    }
+
+    #[test]
+    fn it_rejects_malformed_credentials_files() {
+        let path = env::temp_dir().join("nitox-malformed.creds");
+        fs::write(&path, "-----BEGIN NATS USER JWT-----\neyJ0eXAiOiJqd3QifQ\n------END NATS USER JWT------\n").unwrap();
+
+        let mut cmd = ConnectCommand::builder().build().unwrap();
+        let res = NKeyAuth::from_credentials_file(&path).sign_connect(&mut cmd, "nonce");
+        let _ = fs::remove_file(&path);
+        match res {
+            Err(NatsError::CredentialsError(ref reason)) if reason.contains("no user NKey seed") => {}
+            res => panic!("Expected a malformed credentials file, got {:?}", res),
+        }
+
+        let res = NKeyAuth::from_credentials_file("/nonexistent/nitox.creds").sign_connect(&mut cmd, "nonce");
+        match res {
+            Err(NatsError::CredentialsError(ref reason)) if reason.starts_with("cannot read") => {}
+            res => panic!("Expected an unreadable credentials file, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn it_reads_seed_files() {
+        let key_pair = KeyPair::new_user();
+        let path = env::temp_dir().join(format!("nitox-{}.nk", key_pair.public_key()));
+        fs::write(&path, format!("{}\n", key_pair.seed().unwrap())).unwrap();
+
+        let mut cmd = ConnectCommand::builder().build().unwrap();
+        let res = NKeyAuth::from_seed_file(&path).sign_connect(&mut cmd, "nonce");
+        let _ = fs::remove_file(&path);
+        assert!(res.is_ok());
+
+        let json = ::serde_json::to_value(&cmd).unwrap();
+        assert_eq!(json["nkey"], key_pair.public_key());
+        assert!(key_pair.verify(b"nonce", &signature(&cmd)).is_ok());
+    }
+}
@@ -53,7 +53,25 @@ impl Decoder for OpCodec {
             if let Some(command_body_offset) = buf[command_end..].windows(2).position(|w| w == b"\r\n") {
                 let mut end_buf_pos = command_end + command_body_offset + 2;
 
-                if &buf[..command_end] == b"PUB" || &buf[..command_end] == b"MSG" {
+                if &buf[..command_end] == b"HPUB" || &buf[..command_end] == b"HMSG" {
+                    // Headers contain CRLFs, so the end of the message can only be found with its size, which is the
+                    // last argument of the control line
+                    let control_line = ::std::str::from_utf8(&buf[command_end..end_buf_pos - 2])
+                        .map_err(CommandError::from)?;
+                    let total_len: usize = control_line
+                        .split_whitespace()
+                        .next_back()
+                        .ok_or(CommandError::CommandMalformed)?
+                        .parse()
+                        .map_err(CommandError::from)?;
+
+                    if buf.len() < end_buf_pos + total_len + 2 {
+                        debug!(target: "nitox", "command was incomplete");
+                        return Ok(None);
+                    }
+
+                    end_buf_pos += total_len + 2;
+                } else if &buf[..command_end] == b"PUB" || &buf[..command_end] == b"MSG" {
                     debug!(target: "nitox", "detected PUB or MSG, looking for second CRLF");
                     if let Some(new_end) = buf[end_buf_pos..].windows(2).position(|w| w == b"\r\n") {
                         debug!(target: "nitox", "found second CRLF at position {}", end_buf_pos + new_end + 2);
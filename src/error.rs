@@ -30,15 +30,24 @@ pub enum NatsError {
     /// Occurs if we try to parse a string that is supposed to be valid UTF8 and...is actually not
     #[fail(display = "UTF8Error: {}", _0)]
     UTF8Error(::std::string::FromUtf8Error),
-    /// Error on TLS handling
+    /// Error on TLS handling, coming from whichever TLS backend is enabled
     #[fail(display = "TlsError: {}", _0)]
-    TlsError(::native_tls::Error),
+    TlsError(String),
+    /// Error on the WebSocket connection
+    #[fail(display = "WebSocketError: {}", _0)]
+    WebSocketError(String),
+    /// The proxy refused to open a tunnel to the server, or didn't speak the expected protocol
+    #[fail(display = "ProxyError: {}", _0)]
+    ProxyError(String),
     /// Occurs when the host is not provided, removing the ability for TLS to function correctly for server identify verification
     #[fail(display = "TlsHostMissingError: Host is missing, can't verify server identity")]
     TlsHostMissingError,
     /// Cannot parse an URL
     #[fail(display = "UrlParseError: {}", _0)]
     UrlParseError(::url::ParseError),
+    /// The scheme of a server URL isn't one of `nats://`, `tls://`, `ws://`, `wss://` or `unix://`
+    #[fail(display = "UnsupportedScheme: {}", _0)]
+    UnsupportedScheme(String),
     /// Cannot parse an IP
     #[fail(display = "AddrParseError: {}", _0)]
     AddrParseError(::std::net::AddrParseError),
@@ -47,9 +56,36 @@ pub enum NatsError {
     /// resolving succeeded but gave no results
     #[fail(display = "UriDNSResolveError: {:?}", _0)]
     UriDNSResolveError(Option<io::Error>),
-    /// Cannot reconnect to server after retrying once
+    /// Occurs when the options don't contain any server to connect to
+    #[fail(display = "NoServerAvailable: the server pool is empty")]
+    NoServerAvailable,
+    /// Cannot reconnect to server after exhausting the attempts allowed by the reconnect policy
     #[fail(display = "CannotReconnectToServer: cannot reconnect to server")]
     CannotReconnectToServer,
+    /// The server didn't send its INFO in time after the connection was opened
+    #[fail(display = "HandshakeTimeout: the server didn't send its INFO in time")]
+    HandshakeTimeout,
+    /// The server didn't start the connection with an INFO message
+    #[fail(display = "HandshakeError: {}", _0)]
+    HandshakeError(String),
+    /// The server requires authentication but no credentials have been given in the CONNECT command
+    #[fail(display = "AuthorizationRequired: the server requires credentials")]
+    AuthorizationRequired,
+    /// The NKey seed is invalid or signing the server nonce failed
+    #[fail(display = "NKeyError: {}", _0)]
+    NKeyError(String),
+    /// The credentials or NKey seed file cannot be read or is malformed
+    #[fail(display = "CredentialsError: {}", _0)]
+    CredentialsError(String),
+    /// The connection has been closed by the client, through `NatsClient::close()` or `NatsClient::drain()`
+    #[fail(display = "ConnectionClosed: the connection has been closed")]
+    ConnectionClosed,
+    /// The reconnect buffer is full, the publication is refused until the connection is restored
+    #[fail(
+        display = "ReconnectBufferExceeded: cannot buffer more publications while disconnected (reconnect_buffer_size = {})",
+        _0
+    )]
+    ReconnectBufferExceeded(usize),
     /// Something went wrong in one of the Reciever/Sender pairs
     #[fail(display = "InnerBrokenChain: the sender/receiver pair has been disconnected")]
     InnerBrokenChain,
@@ -59,18 +95,40 @@ pub enum NatsError {
         _0
     )]
     MaxPayloadOverflow(u32),
+    /// The message has headers but the server doesn't support them
+    #[fail(display = "HeadersNotSupported: the server doesn't support headers")]
+    HeadersNotSupported,
+    /// Error coming from the timer driving delays and timeouts
+    #[fail(display = "TimerError: {}", _0)]
+    TimerError(::tokio_timer::Error),
+    /// The server rejected a command with a -ERR message
+    #[fail(display = "ServerError: {}", _0)]
+    ServerError(protocol::commands::ServerError),
     /// Generic string error
     #[fail(display = "GenericError: {}", _0)]
     GenericError(String),
     /// Error thrown when a subscription is fused after reaching the maximum messages
     #[fail(display = "SubscriptionReachedMaxMsgs after {} messages", _0)]
     SubscriptionReachedMaxMsgs(u32),
+    /// Nobody answered the request in time
+    #[fail(display = "RequestTimeout: no reply received in time")]
+    RequestTimeout,
+    /// Nobody listens to the subject of the request, as told by the server with a 503 status
+    #[fail(display = "NoResponders: nobody listens to the subject of the request")]
+    NoResponders,
+    /// The consumer of the subscription doesn't keep up and its pending limits were reached, messages are being
+    /// dropped. Contains the sid of the subscription
+    #[fail(display = "SlowConsumer: messages of subscription {} are being dropped", _0)]
+    SlowConsumer(String),
 }
 
 impl From<io::Error> for NatsError {
     fn from(err: io::Error) -> Self {
         match err.kind() {
-            io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionRefused => {
+            io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe => {
                 NatsError::ServerDisconnected(Some(err))
             }
             _ => NatsError::IOError(err),
@@ -86,7 +144,7 @@ impl<T> From<::futures::sync::mpsc::SendError<T>> for NatsError {
 
 from_error!(protocol::CommandError, NatsError, NatsError::ProtocolError);
 from_error!(::std::string::FromUtf8Error, NatsError, NatsError::UTF8Error);
-from_error!(::native_tls::Error, NatsError, NatsError::TlsError);
 from_error!(String, NatsError, NatsError::GenericError);
 from_error!(::url::ParseError, NatsError, NatsError::UrlParseError);
 from_error!(::std::net::AddrParseError, NatsError, NatsError::AddrParseError);
+from_error!(::tokio_timer::Error, NatsError, NatsError::TimerError);
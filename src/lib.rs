@@ -84,7 +84,9 @@ extern crate serde;
 extern crate serde_derive;
 extern crate serde_json;
 
+extern crate base64;
 extern crate bytes;
+extern crate nkeys;
 extern crate parking_lot;
 extern crate rand;
 
@@ -92,17 +94,35 @@ extern crate rand;
 extern crate log;
 
 extern crate futures;
-extern crate native_tls;
 extern crate tokio_codec;
 extern crate tokio_executor;
+extern crate tokio_io;
 extern crate tokio_tcp;
-extern crate tokio_tls;
+extern crate tokio_timer;
+extern crate tokio_tungstenite;
 extern crate url;
+extern crate zeroize;
+
+#[cfg(unix)]
+extern crate tokio_uds;
+
+#[cfg(feature = "native-tls")]
+extern crate native_tls_crate as native_tls;
+#[cfg(feature = "native-tls")]
+extern crate tokio_tls;
+
+#[cfg(feature = "rustls")]
+extern crate rustls_crate as rustls;
+#[cfg(feature = "rustls")]
+extern crate tokio_rustls;
+#[cfg(feature = "rustls")]
+extern crate webpki;
+#[cfg(feature = "rustls")]
+extern crate webpki_roots;
 
 #[macro_use]
 mod error;
 
-// TODO: Handle verbose mode
 // TODO: Switch parsing to using `nom`
 // TODO: Support NATS Streaming Server
 
@@ -112,6 +132,15 @@ mod protocol;
 pub use self::protocol::*;
 
 pub(crate) mod net;
+pub use self::net::{
+    NatsEvent, ProxyConfig, ProxyConfigBuilder, ProxyCredentials, ProxyKind, ReconnectPolicy, ReconnectPolicyBuilder,
+};
+
+mod auth;
+pub use self::auth::*;
+
+mod tls;
+pub use self::tls::*;
 
 mod client;
 pub use self::client::*;
@@ -1,24 +1,45 @@
 use futures::{
-    future::{self, Either},
+    future::{self, Either, Loop},
     prelude::*,
+    stream,
+    sync::mpsc,
+    task::{self, AtomicTask},
+};
+use parking_lot::{Mutex, RwLock};
+use std::{
+    fmt, mem,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
-use parking_lot::RwLock;
-use std::{net::SocketAddr, sync::Arc};
 use tokio_executor;
+use tokio_timer::Delay;
 
 use error::NatsError;
-use protocol::Op;
+use protocol::{
+    commands::{PubCommand, ServerInfo},
+    Op,
+};
+use tls::TlsConfig;
 
-use super::connection_inner::NatsConnectionInner;
+use super::{
+    connection_inner::NatsConnectionInner,
+    events::{NatsEvent, NatsEventEmitter},
+    proxy::ProxyConfig,
+    reconnect::{ReconnectBuffer, ReconnectPolicy},
+    server_pool::ServerPool,
+};
 
 macro_rules! reco {
-    ($conn:ident) => {
-        *$conn.state.write() = NatsConnectionState::Disconnected;
-
-        tokio_executor::spawn($conn.reconnect().map_err(|e| {
-            debug!(target: "nitox", "Reconnection error: {}", e);
-            ()
-        }));
+    ($conn:ident, $reason:expr) => {
+        if $conn.mark_disconnected($reason) {
+            tokio_executor::spawn($conn.reconnect().map_err(|e| {
+                error!(target: "nitox", "Reconnection error: {}", e);
+                ()
+            }));
+        }
     };
 }
 
@@ -27,53 +48,350 @@ macro_rules! reco {
 pub(crate) enum NatsConnectionState {
     Connected,
     Reconnecting,
+    /// The new connection is up and the replay is being sent, writers wait for it to be done
+    Replaying,
     Disconnected,
+    /// The reconnect policy gave up, the connection is unusable
+    Closed,
+    /// The connection has been closed on purpose by the client
+    Shutdown,
+}
+
+/// Gives the commands to replay, being told which of the acknowledged commands issued while disconnected have been
+/// buffered, the others being sent again by the replay itself
+pub(crate) type ReplayFn = dyn Fn(&[bool]) -> Vec<Op> + Send + Sync;
+
+/// Hook giving the commands to send to the server right after reconnecting, before anything else goes through
+/// the connection (CONNECT, SUBs of the live subscriptions...)
+pub(crate) struct ReconnectReplay(pub(crate) Box<ReplayFn>);
+
+impl fmt::Debug for ReconnectReplay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ReconnectReplay(Fn(&[bool]) -> Vec<Op>)")
+    }
 }
 
 /// Represents a connection to a NATS server. Implements `Sink` and `Stream`
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct NatsConnection {
     /// indicates if the connection is made over TLS
     pub(crate) is_tls: bool,
-    /// Server standardized IP address
-    pub(crate) addr: SocketAddr,
-    /// Host of the server; Only used if connecting to a TLS-enabled server
-    pub(crate) host: Option<String>,
+    /// TLS configuration, used again when reconnecting
+    pub(crate) tls_config: Arc<TlsConfig>,
+    /// Proxy the connections go through, used again when reconnecting
+    pub(crate) proxy: Option<Arc<ProxyConfig>>,
+    /// Servers of the cluster we can connect to
+    pub(crate) pool: Arc<RwLock<ServerPool>>,
+    /// Time given to a server to send its INFO after accepting the connection
+    pub(crate) handshake_timeout: Duration,
+    /// Last INFO sent by the server
+    pub(crate) server_info: Arc<RwLock<Option<ServerInfo>>>,
+    /// Policy followed when the connection is lost
+    pub(crate) reconnect_policy: ReconnectPolicy,
     /// Inner dual `Stream`/`Sink` of the TCP connection
     pub(crate) inner: Arc<RwLock<NatsConnectionInner>>,
     /// Current state of the connection
     pub(crate) state: Arc<RwLock<NatsConnectionState>>,
+    /// Task polling the `Stream` part, woken up once reconnected
+    pub(crate) read_task: Arc<AtomicTask>,
+    /// Task polling the `Sink` part, woken up once reconnected
+    pub(crate) write_task: Arc<AtomicTask>,
+    /// Commands replayed after each successful reconnection
+    pub(crate) replay: Arc<RwLock<Option<ReconnectReplay>>>,
+    /// Publications issued while disconnected
+    pub(crate) reconnect_buffer: Arc<Mutex<ReconnectBuffer>>,
+    /// Listeners of the state transitions of the connection
+    pub(crate) events: Arc<NatsEventEmitter>,
+    /// PINGs written to the current socket that didn't get their PONG yet. Those are lost along with the socket
+    pub(crate) pings_in_flight: Arc<AtomicUsize>,
+    /// Commands written to the current socket that didn't get their +OK/-ERR yet, only relevant in verbose mode
+    pub(crate) acks_in_flight: Arc<AtomicUsize>,
+}
+
+/// Decrements a counter of commands in flight, which might have been reset in the meantime
+fn decrement_in_flight(counter: &AtomicUsize) {
+    let mut in_flight = counter.load(Ordering::SeqCst);
+    while in_flight > 0 {
+        match counter.compare_exchange(in_flight, in_flight - 1, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_) => break,
+            Err(actual) => in_flight = actual,
+        }
+    }
 }
 
 impl NatsConnection {
-    /// Tries to reconnect once to the server; Only used internally. Blocks polling during reconnecting
-    /// by forcing the object to return `Async::NotReady`/`AsyncSink::NotReady`
+    /// Flags the connection as disconnected. Returns `false` if the disconnection is already being taken care of
+    fn mark_disconnected(&self, reason: String) -> bool {
+        {
+            let mut state = self.state.write();
+            if *state != NatsConnectionState::Connected {
+                return false;
+            }
+
+            *state = NatsConnectionState::Disconnected;
+        }
+
+        self.events.emit(NatsEvent::Disconnected(reason));
+        true
+    }
+
+    /// Takes in an INFO sent by the server, during the handshake or later on: the servers it advertises join the
+    /// pool and lame duck mode is reported
+    pub(crate) fn update_server_info(&self, server_info: ServerInfo) {
+        if let Some(ref urls) = server_info.connect_urls {
+            self.pool.write().merge_connect_urls(urls);
+        }
+
+        if server_info.ldm == Some(true) {
+            self.events.emit(NatsEvent::LameDuck);
+        }
+
+        *self.server_info.write() = Some(server_info);
+    }
+
+    /// Registers a listener of the connection events. It's told right away if the connection is currently up
+    pub(crate) fn events(&self) -> mpsc::UnboundedReceiver<NatsEvent> {
+        let initial = if *self.state.read() == NatsConnectionState::Connected {
+            vec![NatsEvent::Connected]
+        } else {
+            vec![]
+        };
+
+        self.events.subscribe(initial)
+    }
+
+    /// Tries to reconnect to the servers of the pool following the reconnect policy; Only used internally.
+    /// Blocks polling during reconnecting by forcing the object to return `Async::NotReady`/`AsyncSink::NotReady`,
+    /// and closes the connection for good when the policy gives up. The previous socket is dropped before the first
+    /// attempt, since some servers only accept a new connection once the previous one is gone
     fn reconnect(&self) -> impl Future<Item = (), Error = NatsError> {
         *self.state.write() = NatsConnectionState::Reconnecting;
 
         let inner_arc = Arc::clone(&self.inner);
+        // The lock might be held by whoever noticed the disconnection, so the socket is dropped once spawned
+        let stale_inner = Arc::clone(&self.inner);
         let inner_state = Arc::clone(&self.state);
+        let pool = Arc::clone(&self.pool);
+        let policy = self.reconnect_policy.clone();
         let is_tls = self.is_tls;
-        let maybe_host = self.host.clone();
-        NatsConnectionInner::connect_tcp(&self.addr)
-            .and_then(move |socket| {
-                if is_tls {
-                    Either::A(
-                        // This unwrap is safe because the value would always be present if `is_tls` is true
-                        NatsConnectionInner::upgrade_tcp_to_tls(&maybe_host.unwrap(), socket)
-                            .map(NatsConnectionInner::from),
-                    )
-                } else {
-                    Either::B(future::ok(NatsConnectionInner::from(socket)))
-                }
-            }).and_then(move |inner| {
-                {
+        let handshake_timeout = self.handshake_timeout;
+        let conn = self.clone();
+        let read_task = Arc::clone(&self.read_task);
+        let write_task = Arc::clone(&self.write_task);
+        let replay_arc = Arc::clone(&self.replay);
+        let buffer_arc = Arc::clone(&self.reconnect_buffer);
+        let events = Arc::clone(&self.events);
+
+        let pool_inner = Arc::clone(&pool);
+        let events_inner = Arc::clone(&events);
+        let state_inner = Arc::clone(&inner_state);
+        future::lazy(move || {
+            *stale_inner.write() = NatsConnectionInner::Disconnected;
+            Ok(())
+        }).and_then(move |_| {
+            future::loop_fn(0u32, move |attempt| {
+                let pool = Arc::clone(&pool_inner);
+                let events = Arc::clone(&events_inner);
+                let replay = Arc::clone(&replay_arc);
+                let buffer = Arc::clone(&buffer_arc);
+                let policy = policy.clone();
+                let conn = conn.clone();
+                let tls_config = Arc::clone(&conn.tls_config);
+                let proxy = conn.proxy.clone();
+                let delay = policy.delay_for(attempt);
+                debug!(target: "nitox", "Reconnection attempt #{} in {:?}", attempt + 1, delay);
+
+                let state = Arc::clone(&state_inner);
+                let replay_state = Arc::clone(&state_inner);
+                let failed_state = Arc::clone(&state_inner);
+                Delay::new(Instant::now() + delay)
+                    .from_err()
+                    .and_then(move |_| {
+                        if *state.read() == NatsConnectionState::Shutdown {
+                            return Either::B(future::err(NatsError::ConnectionClosed));
+                        }
+
+                        events.emit(NatsEvent::Reconnecting(attempt + 1));
+                        let server = pool.write().next_server();
+                        match server {
+                            Some(server) => Either::A(NatsConnectionInner::connect(
+                                &server,
+                                is_tls,
+                                tls_config,
+                                proxy,
+                                handshake_timeout,
+                            )),
+                            None => Either::B(future::err(NatsError::NoServerAvailable)),
+                        }
+                    }).and_then(move |(inner, server_info)| {
+                        conn.update_server_info(server_info);
+                        // The new server doesn't know anything about us, so we tell it again before letting anything
+                        // else through
+                        // Writers are held back from now on, so nothing lands in the buffer once it's been drained
+                        let drained = {
+                            let mut buffer = buffer.lock();
+                            let mut state = replay_state.write();
+                            if *state == NatsConnectionState::Shutdown {
+                                None
+                            } else {
+                                *state = NatsConnectionState::Replaying;
+                                Some((buffer.drain(), buffer.take_acks()))
+                            }
+                        };
+                        let (buffered, acks) = match drained {
+                            Some(drained) => drained,
+                            None => return Either::B(future::err(NatsError::ConnectionClosed)),
+                        };
+                        let mut ops = match *replay.read() {
+                            Some(ref replay) => (replay.0)(&acks),
+                            None => vec![],
+                        };
+                        ops.extend(buffered);
+
+                        debug!(target: "nitox", "Replaying {} commands after reconnection", ops.len());
+                        // Not `send_all`, which closes the sink once done: a TLS close_notify or a WebSocket Close
+                        // frame would end the new session right away
+                        Either::A(stream::iter_ok::<_, NatsError>(ops).fold(inner, |inner, op| inner.send(op)))
+                    }).then(move |res| match res {
+                        Ok(inner) => Ok(Loop::Break(inner)),
+                        Err(NatsError::ConnectionClosed) => Err(NatsError::ConnectionClosed),
+                        Err(e) => {
+                            {
+                                let mut state = failed_state.write();
+                                if *state == NatsConnectionState::Replaying {
+                                    *state = NatsConnectionState::Reconnecting;
+                                }
+                            }
+
+                            debug!(target: "nitox", "Reconnection attempt #{} failed: {}", attempt + 1, e);
+                            if policy.should_retry(attempt + 1) {
+                                Ok(Loop::Continue(attempt + 1))
+                            } else {
+                                Err(NatsError::CannotReconnectToServer)
+                            }
+                        }
+                    })
+            })
+        }).then(move |res| {
+            let mut state = inner_state.write();
+            // The client closed the connection in the meantime, the new socket is dropped right away
+            if *state == NatsConnectionState::Shutdown {
+                return Err(NatsError::ConnectionClosed);
+            }
+
+            let res = match res {
+                Ok(inner) => {
                     *inner_arc.write() = inner;
-                    *inner_state.write() = NatsConnectionState::Connected;
+                    *state = NatsConnectionState::Connected;
+                    drop(state);
+                    let uri = pool.read().current().map(|server| server.uri.clone()).unwrap_or_default();
+                    debug!(target: "nitox", "Successfully swapped reconnected underlying connection to {}", uri);
+                    events.emit(NatsEvent::Reconnected(uri));
+                    Ok(())
                 }
-                debug!(target: "nitox", "Successfully swapped reconnected underlying connection");
-                Ok(())
-            })
+                Err(e) => {
+                    *state = NatsConnectionState::Closed;
+                    drop(state);
+                    events.emit(NatsEvent::Closed);
+                    Err(e)
+                }
+            };
+
+            read_task.notify();
+            write_task.notify();
+            res
+        })
+    }
+
+    /// Checks if a publication can go through: always when connected, and only if there's room left in the reconnect
+    /// buffer otherwise
+    pub(crate) fn check_publish(&self, cmd: &PubCommand) -> Result<(), NatsError> {
+        // Not holding the state while locking the buffer, the reconnection locks them the other way around
+        let state = *self.state.read();
+        match state {
+            NatsConnectionState::Connected => Ok(()),
+            NatsConnectionState::Closed => Err(NatsError::CannotReconnectToServer),
+            NatsConnectionState::Shutdown => Err(NatsError::ConnectionClosed),
+            _ => self.reconnect_buffer.lock().check_room_for(cmd),
+        }
+    }
+
+    /// Handles the commands sent while the connection is down: publications are kept in the reconnect buffer as long
+    /// as it has room, along with UNSUBs so that someone waiting for their +OK gets it. SUBs and CONNECTs are sent
+    /// again by the replay. PINGs wait for the new connection since someone might be waiting for their PONG.
+    /// Everything else is dropped
+    fn buffer_while_disconnected(&self, item: Op) -> StartSend<Op, NatsError> {
+        if item == Op::PING {
+            return Ok(AsyncSink::NotReady(item));
+        }
+
+        if !item.is_acknowledged() {
+            debug!(target: "nitox", "Dropping {:?} while disconnected", item);
+            return Ok(AsyncSink::Ready);
+        }
+
+        let mut buffer = self.reconnect_buffer.lock();
+        // The buffer has been drained for the replay, whatever is pushed now would be left behind
+        if *self.state.read() == NatsConnectionState::Replaying {
+            return Ok(AsyncSink::NotReady(item));
+        }
+
+        Ok(buffer.push(item))
+    }
+
+    /// Current state of the connection
+    pub(crate) fn state(&self) -> NatsConnectionState {
+        *self.state.read()
+    }
+
+    /// Drops the current connection and reconnects, for when the connection is deemed unusable even though the
+    /// socket didn't report any error
+    pub(crate) fn force_reconnect(&self, reason: String) {
+        reco!(self, reason);
+    }
+
+    /// Checks if the connection can be used, registering the current task to be woken up once reconnected. The task
+    /// might be blocked on the socket we're about to drop, so the registration has to happen even when connected.
+    /// Returns an error if the connection has been closed
+    fn poll_state(&self, task: &AtomicTask) -> Result<bool, NatsError> {
+        task.register();
+        match *self.state.read() {
+            NatsConnectionState::Connected => Ok(true),
+            NatsConnectionState::Closed => Err(NatsError::CannotReconnectToServer),
+            NatsConnectionState::Shutdown => Err(NatsError::ConnectionClosed),
+            _ => Ok(false),
+        }
+    }
+
+    /// Closes the connection for good: pending writes are flushed and the socket is shut down. Whatever is sent
+    /// afterwards fails with `NatsError::ConnectionClosed`, and the `Stream` part ends
+    pub(crate) fn close(&self) -> impl Future<Item = (), Error = NatsError> {
+        let previous = mem::replace(&mut *self.state.write(), NatsConnectionState::Shutdown);
+        self.read_task.notify();
+        self.write_task.notify();
+
+        match previous {
+            NatsConnectionState::Shutdown => return Either::A(future::ok(())),
+            NatsConnectionState::Closed => {}
+            _ => self.events.emit(NatsEvent::Closed),
+        }
+
+        if previous != NatsConnectionState::Connected {
+            return Either::A(future::ok(()));
+        }
+
+        let inner = Arc::clone(&self.inner);
+        Either::B(future::poll_fn(move || match inner.try_write() {
+            Some(mut inner) => match inner.close() {
+                // The server might have hung up first, which is fine since we're leaving anyway
+                Err(NatsError::ServerDisconnected(_)) => Ok(Async::Ready(())),
+                poll_res => poll_res,
+            },
+            None => {
+                task::current().notify();
+                Ok(Async::NotReady)
+            }
+        }))
     }
 }
 
@@ -82,43 +400,48 @@ impl Sink for NatsConnection {
     type SinkItem = Op;
 
     fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
-        if match self.state.try_read() {
-            Some(state) => *state != NatsConnectionState::Connected,
-            _ => true,
-        } {
-            return Ok(AsyncSink::NotReady(item));
+        if !self.poll_state(&self.write_task)? {
+            return self.buffer_while_disconnected(item);
         }
 
         if let Some(mut inner) = self.inner.try_write() {
             match inner.start_send(item.clone()) {
-                Err(NatsError::ServerDisconnected(_)) => {
-                    reco!(self);
+                Err(e @ NatsError::ServerDisconnected(_)) => {
+                    reco!(self, e.to_string());
                     Ok(AsyncSink::NotReady(item))
                 }
+                Ok(AsyncSink::Ready) => {
+                    if item == Op::PING {
+                        self.pings_in_flight.fetch_add(1, Ordering::SeqCst);
+                    } else if item.is_acknowledged() {
+                        self.acks_in_flight.fetch_add(1, Ordering::SeqCst);
+                    }
+
+                    Ok(AsyncSink::Ready)
+                }
                 poll_res => poll_res,
             }
         } else {
+            task::current().notify();
             Ok(AsyncSink::NotReady(item))
         }
     }
 
     fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
-        if match self.state.try_read() {
-            Some(state) => *state != NatsConnectionState::Connected,
-            _ => true,
-        } {
+        if !self.poll_state(&self.write_task)? {
             return Ok(Async::NotReady);
         }
 
         if let Some(mut inner) = self.inner.try_write() {
             match inner.poll_complete() {
-                Err(NatsError::ServerDisconnected(_)) => {
-                    reco!(self);
+                Err(e @ NatsError::ServerDisconnected(_)) => {
+                    reco!(self, e.to_string());
                     Ok(Async::NotReady)
                 }
                 poll_res => poll_res,
             }
         } else {
+            task::current().notify();
             Ok(Async::NotReady)
         }
     }
@@ -129,22 +452,44 @@ impl Stream for NatsConnection {
     type Item = Op;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        if match self.state.try_read() {
-            Some(state) => *state != NatsConnectionState::Connected,
-            _ => true,
-        } {
-            return Ok(Async::NotReady);
+        match self.poll_state(&self.read_task) {
+            Ok(true) => {}
+            Ok(false) => return Ok(Async::NotReady),
+            // Closed on purpose, so it's the regular end of the stream
+            Err(NatsError::ConnectionClosed) => return Ok(Async::Ready(None)),
+            Err(e) => return Err(e),
         }
 
         if let Some(mut inner) = self.inner.try_write() {
             match inner.poll() {
-                Err(NatsError::ServerDisconnected(_)) => {
-                    reco!(self);
+                Err(e @ NatsError::ServerDisconnected(_)) => {
+                    reco!(self, e.to_string());
+                    Ok(Async::NotReady)
+                }
+                // The server closed the socket on us, it's a disconnection as well
+                Ok(Async::Ready(None)) => {
+                    reco!(self, "connection closed by the server".to_string());
                     Ok(Async::NotReady)
                 }
+                Ok(Async::Ready(Some(Op::PONG))) => {
+                    decrement_in_flight(&self.pings_in_flight);
+                    Ok(Async::Ready(Some(Op::PONG)))
+                }
+                Ok(Async::Ready(Some(Op::OK))) => {
+                    decrement_in_flight(&self.acks_in_flight);
+                    Ok(Async::Ready(Some(Op::OK)))
+                }
+                Ok(Async::Ready(Some(Op::ERR(err)))) => {
+                    if err.replaces_ack() {
+                        decrement_in_flight(&self.acks_in_flight);
+                    }
+
+                    Ok(Async::Ready(Some(Op::ERR(err))))
+                }
                 poll_res => poll_res,
             }
         } else {
+            task::current().notify();
             Ok(Async::NotReady)
         }
     }
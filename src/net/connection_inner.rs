@@ -1,39 +1,228 @@
 use codec::OpCodec;
-use futures::prelude::*;
-use native_tls::TlsConnector as NativeTlsConnector;
-use protocol::Op;
-use std::net::SocketAddr;
+use futures::{
+    future::{self, Either},
+    prelude::*,
+};
+use protocol::{commands::ServerInfo, Op};
+use std::{sync::Arc, time::Duration};
 use tokio_codec::{Decoder, Framed};
 use tokio_tcp::TcpStream;
-use tokio_tls::{TlsConnector, TlsStream};
+use tokio_timer::Timeout;
+use tokio_tungstenite::client_async;
+#[cfg(unix)]
+use tokio_uds::UnixStream;
+use url::Url;
 
 use error::NatsError;
+use tls::{TlsConfig, TlsStream};
 
-/// Inner raw stream enum over TCP and TLS/TCP
+use super::{
+    proxy::{ProxyConfig, ProxyTarget},
+    server_pool::{Server, ServerScheme},
+    ws::{MaybeTlsStream, WsFramed},
+};
+
+/// Inner raw stream enum over TCP, TLS/TCP, WebSocket and Unix sockets
 #[derive(Debug)]
 pub(crate) enum NatsConnectionInner {
     /// Raw TCP Stream framed connection
     Tcp(Box<Framed<TcpStream, OpCodec>>),
     /// TLS over TCP Stream framed connection
     Tls(Box<Framed<TlsStream<TcpStream>, OpCodec>>),
+    /// WebSocket framed connection, with or without TLS
+    Ws(Box<WsFramed<MaybeTlsStream>>),
+    /// Unix domain socket framed connection
+    #[cfg(unix)]
+    Unix(Box<Framed<UnixStream, OpCodec>>),
+    /// No socket: the previous one has been dropped and we're reconnecting
+    Disconnected,
 }
 
 impl NatsConnectionInner {
-    /// Connects to a TCP socket
-    pub(crate) fn connect_tcp(addr: &SocketAddr) -> impl Future<Item = TcpStream, Error = NatsError> {
-        debug!(target: "nitox", "Connecting to {} through TCP", addr);
-        TcpStream::connect(addr).from_err()
+    /// Connects to a server of the pool and waits for its INFO, upgrading the connection to TLS if either the client
+    /// or the server requires it
+    pub(crate) fn connect(
+        server: &Server,
+        tls_required: bool,
+        tls_config: Arc<TlsConfig>,
+        proxy: Option<Arc<ProxyConfig>>,
+        handshake_timeout: Duration,
+    ) -> impl Future<Item = (Self, ServerInfo), Error = NatsError> {
+        debug!(target: "nitox", "Connecting to {} (discovered: {})", server.uri, server.is_implicit);
+        match server.scheme {
+            ServerScheme::Ws | ServerScheme::Wss => Either::A(NatsConnectionInner::connect_ws_server(
+                server,
+                tls_required,
+                tls_config,
+                proxy,
+                handshake_timeout,
+            )),
+            ServerScheme::Unix => Either::B(Either::A(NatsConnectionInner::connect_unix_server(
+                server,
+                tls_required,
+                handshake_timeout,
+            ))),
+            ServerScheme::Nats | ServerScheme::Tls => Either::B(Either::B(NatsConnectionInner::connect_tcp_server(
+                server,
+                tls_required,
+                tls_config,
+                proxy,
+                handshake_timeout,
+            ))),
+        }
+    }
+
+    /// Connects to a server through TCP, upgrading to TLS if needed
+    fn connect_tcp_server(
+        server: &Server,
+        tls_required: bool,
+        tls_config: Arc<TlsConfig>,
+        proxy: Option<Arc<ProxyConfig>>,
+        handshake_timeout: Duration,
+    ) -> impl Future<Item = (Self, ServerInfo), Error = NatsError> {
+        let tls_required = tls_required || server.scheme.is_tls();
+        let host = server.host().to_string();
+        NatsConnectionInner::connect_tcp(server, proxy)
+            .and_then(move |socket| {
+                NatsConnectionInner::read_info(OpCodec::default().framed(socket), handshake_timeout)
+            })
+            .and_then(move |(framed, info)| {
+                if !tls_required && info.tls_required != Some(true) {
+                    return Either::A(future::ok((NatsConnectionInner::Tcp(Box::new(framed)), info)));
+                }
+
+                debug!(target: "nitox", "Connected through TCP, upgrading to TLS");
+                Either::B(
+                    NatsConnectionInner::upgrade_tcp_to_tls(&host, framed.into_inner(), &tls_config)
+                        .map(move |socket| (NatsConnectionInner::from(socket), info)),
+                )
+            })
+    }
+
+    /// Connects to the WebSocket listener of a server, over TLS if needed. Unlike with plain TCP, TLS is set up
+    /// before the WebSocket handshake
+    fn connect_ws_server(
+        server: &Server,
+        tls_required: bool,
+        tls_config: Arc<TlsConfig>,
+        proxy: Option<Arc<ProxyConfig>>,
+        handshake_timeout: Duration,
+    ) -> impl Future<Item = (Self, ServerInfo), Error = NatsError> {
+        let url = match Url::parse(&server.uri) {
+            Ok(url) => url,
+            Err(e) => return Either::A(future::err(e.into())),
+        };
+
+        let tls_required = tls_required || server.scheme.is_tls();
+        let host = server.host().to_string();
+        Either::B(
+            NatsConnectionInner::connect_tcp(server, proxy)
+                .and_then(move |socket| {
+                    if !tls_required {
+                        return Either::A(future::ok(MaybeTlsStream::Plain(socket)));
+                    }
+
+                    Either::B(
+                        NatsConnectionInner::upgrade_tcp_to_tls(&host, socket, &tls_config).map(MaybeTlsStream::Tls),
+                    )
+                }).and_then(move |socket| {
+                    debug!(target: "nitox", "Connecting to {} through WebSocket", url);
+                    client_async(url, socket).from_err()
+                }).and_then(move |(ws, _)| NatsConnectionInner::read_info(WsFramed::new(ws), handshake_timeout))
+                .map(|(framed, info)| (NatsConnectionInner::Ws(Box::new(framed)), info)),
+        )
+    }
+
+    /// Connects to a server through a Unix domain socket. TLS isn't available over Unix sockets, access control
+    /// relies on the permissions of the socket file instead
+    #[cfg(unix)]
+    fn connect_unix_server(
+        server: &Server,
+        tls_required: bool,
+        handshake_timeout: Duration,
+    ) -> impl Future<Item = (Self, ServerInfo), Error = NatsError> {
+        let path = server.path.clone().unwrap_or_default();
+        debug!(target: "nitox", "Connecting to {} through Unix socket", path.display());
+        UnixStream::connect(&path)
+            .from_err()
+            .and_then(move |socket| {
+                NatsConnectionInner::read_info(OpCodec::default().framed(socket), handshake_timeout)
+            })
+            .and_then(move |(framed, info)| {
+                if tls_required || info.tls_required == Some(true) {
+                    return Err(NatsError::TlsError("TLS isn't supported over Unix sockets".into()));
+                }
+
+                Ok((NatsConnectionInner::Unix(Box::new(framed)), info))
+            })
+    }
+
+    #[cfg(not(unix))]
+    fn connect_unix_server(
+        _server: &Server,
+        _tls_required: bool,
+        _handshake_timeout: Duration,
+    ) -> impl Future<Item = (Self, ServerInfo), Error = NatsError> {
+        future::err(NatsError::UnsupportedScheme(
+            "Unix sockets are only available on Unix platforms".into(),
+        ))
+    }
+
+    /// Waits for the INFO the server sends right after accepting the connection
+    fn read_info<S>(framed: S, handshake_timeout: Duration) -> impl Future<Item = (S, ServerInfo), Error = NatsError>
+    where
+        S: Stream<Item = Op, Error = NatsError>,
+    {
+        Timeout::new(framed.into_future().map_err(|(e, _)| e), handshake_timeout)
+            .map_err(|e| {
+                if e.is_elapsed() {
+                    NatsError::HandshakeTimeout
+                } else if e.is_timer() {
+                    e.into_timer().map_or(NatsError::HandshakeTimeout, NatsError::from)
+                } else {
+                    e.into_inner().unwrap_or(NatsError::HandshakeTimeout)
+                }
+            }).and_then(|(op, framed)| match op {
+                Some(Op::INFO(info)) => Ok((framed, info)),
+                Some(op) => Err(NatsError::HandshakeError(format!("expected INFO, got {:?}", op))),
+                None => Err(NatsError::ServerDisconnected(None)),
+            })
+    }
+
+    /// Connects to the server through TCP, tunneling through the proxy if there's one. The host is resolved by the
+    /// proxy rather than locally if it's configured to
+    pub(crate) fn connect_tcp(
+        server: &Server,
+        proxy: Option<Arc<ProxyConfig>>,
+    ) -> impl Future<Item = TcpStream, Error = NatsError> {
+        if let Some(ref proxy) = proxy {
+            if proxy.remote_dns {
+                let target = ProxyTarget::Host(server.host().to_string(), server.port);
+                return Either::A(Either::A(proxy.connect(target)));
+            }
+        }
+
+        let addr = match server.resolve() {
+            Ok(addr) => addr,
+            Err(e) => return Either::B(Either::A(future::err(e))),
+        };
+
+        match proxy {
+            Some(proxy) => Either::A(Either::B(proxy.connect(ProxyTarget::Addr(addr)))),
+            None => {
+                debug!(target: "nitox", "Connecting to {} through TCP", addr);
+                Either::B(Either::B(TcpStream::connect(&addr).from_err()))
+            }
+        }
     }
 
     /// Upgrades an existing TCP socket to TLS over TCP
     pub(crate) fn upgrade_tcp_to_tls(
         host: &str,
         socket: TcpStream,
+        tls_config: &TlsConfig,
     ) -> impl Future<Item = TlsStream<TcpStream>, Error = NatsError> {
-        let tls_connector = NativeTlsConnector::builder().build().unwrap();
-        let tls_stream: TlsConnector = tls_connector.into();
-        debug!(target: "nitox", "Connecting to {} through TLS over TCP", host);
-        tls_stream.connect(&host, socket).from_err()
+        tls_config.upgrade(host, socket)
     }
 }
 
@@ -57,6 +246,10 @@ impl Sink for NatsConnectionInner {
         match self {
             NatsConnectionInner::Tcp(framed) => framed.start_send(item),
             NatsConnectionInner::Tls(framed) => framed.start_send(item),
+            NatsConnectionInner::Ws(framed) => framed.start_send(item),
+            #[cfg(unix)]
+            NatsConnectionInner::Unix(framed) => framed.start_send(item),
+            NatsConnectionInner::Disconnected => Err(NatsError::ServerDisconnected(None)),
         }
     }
 
@@ -64,6 +257,21 @@ impl Sink for NatsConnectionInner {
         match self {
             NatsConnectionInner::Tcp(framed) => framed.poll_complete(),
             NatsConnectionInner::Tls(framed) => framed.poll_complete(),
+            NatsConnectionInner::Ws(framed) => framed.poll_complete(),
+            #[cfg(unix)]
+            NatsConnectionInner::Unix(framed) => framed.poll_complete(),
+            NatsConnectionInner::Disconnected => Err(NatsError::ServerDisconnected(None)),
+        }
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        match self {
+            NatsConnectionInner::Tcp(framed) => framed.close(),
+            NatsConnectionInner::Tls(framed) => framed.close(),
+            NatsConnectionInner::Ws(framed) => framed.close(),
+            #[cfg(unix)]
+            NatsConnectionInner::Unix(framed) => framed.close(),
+            NatsConnectionInner::Disconnected => Ok(Async::Ready(())),
         }
     }
 }
@@ -76,6 +284,10 @@ impl Stream for NatsConnectionInner {
         match self {
             NatsConnectionInner::Tcp(framed) => framed.poll(),
             NatsConnectionInner::Tls(framed) => framed.poll(),
+            NatsConnectionInner::Ws(framed) => framed.poll(),
+            #[cfg(unix)]
+            NatsConnectionInner::Unix(framed) => framed.poll(),
+            NatsConnectionInner::Disconnected => Err(NatsError::ServerDisconnected(None)),
         }
     }
 }
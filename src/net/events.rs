@@ -0,0 +1,51 @@
+use futures::sync::mpsc;
+use parking_lot::Mutex;
+
+use protocol::commands::ServerError;
+
+/// Lifecycle events of the connection to the server, as returned by `NatsClient::events()`
+#[derive(Debug, Clone, PartialEq)]
+pub enum NatsEvent {
+    /// The client is connected to the server
+    Connected,
+    /// The connection to the server has been lost, contains the reason why
+    Disconnected(String),
+    /// The client is trying to reconnect, contains the number of the attempt starting at 1
+    Reconnecting(u32),
+    /// The client reconnected, contains the URI of the server
+    Reconnected(String),
+    /// The server entered lame duck mode and is about to shut down, a reconnection will follow shortly
+    LameDuck,
+    /// The connection is closed for good
+    Closed,
+    /// The server sent an -ERR message
+    ServerError(ServerError),
+    /// A subscription reached its pending limits and its messages are being dropped, contains its sid
+    SlowConsumer(String),
+}
+
+/// Broadcasts the events to every listener, forgetting about the ones that went away
+#[derive(Debug, Default)]
+pub(crate) struct NatsEventEmitter {
+    listeners: Mutex<Vec<mpsc::UnboundedSender<NatsEvent>>>,
+}
+
+impl NatsEventEmitter {
+    /// Registers a new listener, which will first receive the given events
+    pub(crate) fn subscribe(&self, initial: Vec<NatsEvent>) -> mpsc::UnboundedReceiver<NatsEvent> {
+        let (tx, rx) = mpsc::unbounded();
+        for event in initial {
+            let _ = tx.unbounded_send(event);
+        }
+
+        self.listeners.lock().push(tx);
+        rx
+    }
+
+    pub(crate) fn emit(&self, event: NatsEvent) {
+        debug!(target: "nitox", "Emitting event {:?}", event);
+        self.listeners
+            .lock()
+            .retain(|tx| tx.unbounded_send(event.clone()).is_ok());
+    }
+}
@@ -1,47 +1,116 @@
-use futures::prelude::*;
-use parking_lot::RwLock;
-use std::net::SocketAddr;
-use std::sync::Arc;
+use futures::{
+    future::{self, Either, Loop},
+    prelude::*,
+    task::AtomicTask,
+};
+use parking_lot::{Mutex, RwLock};
+use std::{
+    sync::{atomic::AtomicUsize, Arc},
+    time::Duration,
+};
 
 pub(crate) mod connection;
 mod connection_inner;
+pub(crate) mod events;
+pub(crate) mod proxy;
+pub(crate) mod reconnect;
+pub(crate) mod server_pool;
+mod ws;
 
 use error::NatsError;
+use protocol::commands::ServerInfo;
+use tls::TlsConfig;
 
-use self::connection::NatsConnectionState;
 use self::connection_inner::*;
+use self::events::NatsEventEmitter;
+use self::reconnect::ReconnectBuffer;
 
-pub(crate) use self::connection::NatsConnection;
+pub(crate) use self::connection::{NatsConnection, NatsConnectionState, ReconnectReplay};
+pub use self::events::NatsEvent;
+pub use self::proxy::{ProxyConfig, ProxyConfigBuilder, ProxyCredentials, ProxyKind};
+pub use self::reconnect::{ReconnectPolicy, ReconnectPolicyBuilder};
+pub(crate) use self::server_pool::{Server, ServerPool, UrlCredentials};
 
-/// Connect to a raw TCP socket
-pub(crate) fn connect(addr: SocketAddr) -> impl Future<Item = NatsConnection, Error = NatsError> {
-    NatsConnectionInner::connect_tcp(&addr).map(move |socket| {
-        debug!(target: "nitox", "Connected through TCP");
-        NatsConnection {
-            is_tls: false,
-            addr,
-            host: None,
-            state: Arc::new(RwLock::new(NatsConnectionState::Connected)),
-            inner: Arc::new(RwLock::new(socket.into())),
-        }
+/// Tries every server of the pool once, in a round-robin fashion, until one of them accepts the connection and
+/// completes the handshake
+pub(crate) fn connect_to_pool(
+    pool: Arc<RwLock<ServerPool>>,
+    tls_required: bool,
+    tls_config: Arc<TlsConfig>,
+    proxy: Option<Arc<ProxyConfig>>,
+    handshake_timeout: Duration,
+) -> impl Future<Item = (NatsConnectionInner, ServerInfo), Error = NatsError> {
+    let attempts = pool.read().len();
+    future::loop_fn((pool, 0usize), move |(pool, attempt)| {
+        let server = match pool.write().next_server() {
+            Some(server) => server,
+            None => return Either::A(future::err(NatsError::NoServerAvailable)),
+        };
+
+        let connecting = NatsConnectionInner::connect(
+            &server,
+            tls_required,
+            Arc::clone(&tls_config),
+            proxy.clone(),
+            handshake_timeout,
+        );
+        Either::B(connecting.then(move |res| match res {
+            Ok(connected) => Ok(Loop::Break(connected)),
+            Err(e) => {
+                debug!(target: "nitox", "Cannot connect to {}: {}", server.uri, e);
+                if attempt + 1 >= attempts {
+                    Err(e)
+                } else {
+                    Ok(Loop::Continue((pool, attempt + 1)))
+                }
+            }
+        }))
     })
 }
 
-/// Connect to a TLS over TCP socket. Upgrade is performed automatically
-pub(crate) fn connect_tls(host: String, addr: SocketAddr) -> impl Future<Item = NatsConnection, Error = NatsError> {
-    let inner_host = host.clone();
-    NatsConnectionInner::connect_tcp(&addr)
-        .and_then(move |socket| {
-            debug!(target: "nitox", "Connected through TCP, upgrading to TLS");
-            NatsConnectionInner::upgrade_tcp_to_tls(&host, socket)
-        }).map(move |socket| {
-            debug!(target: "nitox", "Connected through TCP over TLS");
-            NatsConnection {
-                is_tls: true,
-                addr,
-                host: Some(inner_host),
-                state: Arc::new(RwLock::new(NatsConnectionState::Connected)),
-                inner: Arc::new(RwLock::new(socket.into())),
-            }
-        })
+/// Connect to the first available server of the pool and wait for its INFO. Upgrade to TLS is performed automatically
+/// if required by either side
+pub(crate) fn connect(
+    pool: ServerPool,
+    tls_required: bool,
+    tls_config: TlsConfig,
+    proxy: Option<ProxyConfig>,
+    handshake_timeout: Duration,
+    reconnect_policy: ReconnectPolicy,
+    reconnect_buffer_size: usize,
+) -> impl Future<Item = NatsConnection, Error = NatsError> {
+    let pool = Arc::new(RwLock::new(pool));
+    let tls_config = Arc::new(tls_config);
+    let proxy = proxy.map(Arc::new);
+    let connecting = connect_to_pool(
+        Arc::clone(&pool),
+        tls_required,
+        Arc::clone(&tls_config),
+        proxy.clone(),
+        handshake_timeout,
+    );
+    connecting.map(move |(inner, server_info)| {
+        debug!(target: "nitox", "Connected to {:?}", pool.read().current());
+        let connection = NatsConnection {
+            is_tls: tls_required,
+            tls_config,
+            proxy,
+            pool,
+            handshake_timeout,
+            server_info: Arc::new(RwLock::new(None)),
+            reconnect_policy,
+            state: Arc::new(RwLock::new(NatsConnectionState::Connected)),
+            inner: Arc::new(RwLock::new(inner)),
+            read_task: Arc::new(AtomicTask::new()),
+            write_task: Arc::new(AtomicTask::new()),
+            replay: Arc::new(RwLock::new(None)),
+            reconnect_buffer: Arc::new(Mutex::new(ReconnectBuffer::new(reconnect_buffer_size))),
+            events: Arc::new(NatsEventEmitter::default()),
+            pings_in_flight: Arc::new(AtomicUsize::new(0)),
+            acks_in_flight: Arc::new(AtomicUsize::new(0)),
+        };
+
+        connection.update_server_info(server_info);
+        connection
+    })
 }
@@ -0,0 +1,318 @@
+use base64;
+use futures::{
+    future::{self, Either, Loop},
+    prelude::*,
+};
+use std::{
+    fmt,
+    net::{SocketAddr, ToSocketAddrs},
+};
+use tokio_io::io::{read_exact, write_all};
+use tokio_tcp::TcpStream;
+
+use error::NatsError;
+
+/// Maximum size of the response of an HTTP proxy to the CONNECT request
+const MAX_HTTP_RESPONSE_LEN: usize = 8 * 1024;
+
+const SOCKS5_VERSION: u8 = 5;
+const SOCKS5_NO_AUTH: u8 = 0;
+const SOCKS5_USER_PASS_AUTH: u8 = 2;
+
+/// Protocol spoken by the proxy
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProxyKind {
+    /// HTTP proxy supporting the CONNECT method
+    Http,
+    /// SOCKS5 proxy
+    Socks5,
+}
+
+/// Credentials sent to the proxy, as basic auth for HTTP and username/password auth for SOCKS5
+#[derive(Clone, PartialEq)]
+pub struct ProxyCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl ProxyCredentials {
+    pub fn new<U: Into<String>, P: Into<String>>(username: U, password: P) -> Self {
+        ProxyCredentials {
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+}
+
+impl fmt::Debug for ProxyCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProxyCredentials")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+/// Proxy the TCP connections to the servers go through. The tunnel is set up before the TLS upgrade, so TLS is
+/// end-to-end with the server.
+#[derive(Debug, Clone, PartialEq, Builder)]
+#[builder(setter(into))]
+pub struct ProxyConfig {
+    pub kind: ProxyKind,
+    /// Address of the proxy in the HOST:PORT format
+    pub address: String,
+    /// Credentials sent to the proxy, if it requires authentication
+    #[builder(default)]
+    pub credentials: Option<ProxyCredentials>,
+    /// Lets the proxy resolve the host of the server instead of resolving it locally, for hosts only known on the
+    /// other side of the proxy
+    #[builder(default)]
+    pub remote_dns: bool,
+}
+
+/// Where the proxy should connect to
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ProxyTarget {
+    /// Address resolved locally
+    Addr(SocketAddr),
+    /// Host resolved by the proxy, along with the port
+    Host(String, u16),
+}
+
+impl fmt::Display for ProxyTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProxyTarget::Addr(addr) => write!(f, "{}", addr),
+            ProxyTarget::Host(host, port) if host.contains(':') => write!(f, "[{}]:{}", host, port),
+            ProxyTarget::Host(host, port) => write!(f, "{}:{}", host, port),
+        }
+    }
+}
+
+impl ProxyConfig {
+    pub fn builder() -> ProxyConfigBuilder {
+        ProxyConfigBuilder::default()
+    }
+
+    /// Connects to the proxy and opens a tunnel to the target
+    pub(crate) fn connect(&self, target: ProxyTarget) -> impl Future<Item = TcpStream, Error = NatsError> {
+        let proxy_addr = match self.address.to_socket_addrs() {
+            Ok(mut addrs) => match addrs.next() {
+                Some(addr) => addr,
+                None => return Either::A(future::err(NatsError::UriDNSResolveError(None))),
+            },
+            Err(e) => return Either::A(future::err(NatsError::UriDNSResolveError(Some(e)))),
+        };
+
+        debug!(target: "nitox", "Connecting to {} through the {:?} proxy {}", target, self.kind, proxy_addr);
+        let kind = self.kind;
+        let credentials = self.credentials.clone();
+        Either::B(
+            TcpStream::connect(&proxy_addr)
+                .from_err()
+                .and_then(move |socket| match kind {
+                    ProxyKind::Http => Either::A(http_connect(socket, &target, credentials.as_ref())),
+                    ProxyKind::Socks5 => Either::B(socks5_connect(socket, &target, credentials)),
+                }),
+        )
+    }
+}
+
+/// Opens a tunnel with the CONNECT method
+fn http_connect(
+    socket: TcpStream,
+    target: &ProxyTarget,
+    credentials: Option<&ProxyCredentials>,
+) -> impl Future<Item = TcpStream, Error = NatsError> {
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+    if let Some(credentials) = credentials {
+        let token = base64::encode(&format!("{}:{}", credentials.username, credentials.password));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+    }
+
+    request.push_str("\r\n");
+    write_all(socket, request.into_bytes())
+        .from_err()
+        .and_then(|(socket, _)| read_http_response(socket))
+        .and_then(|(socket, response)| {
+            let status_line = response.lines().next().unwrap_or_default().to_string();
+            if status_line.split_whitespace().nth(1) == Some("200") {
+                Ok(socket)
+            } else {
+                Err(NatsError::ProxyError(format!("the proxy refused the tunnel: {}", status_line)))
+            }
+        })
+}
+
+/// Reads the response headers byte by byte, so nothing sent by the server right after gets consumed
+fn read_http_response(socket: TcpStream) -> impl Future<Item = (TcpStream, String), Error = NatsError> {
+    future::loop_fn((socket, Vec::new()), |(socket, mut response)| {
+        read_exact(socket, [0u8; 1]).from_err().and_then(move |(socket, byte)| {
+            response.push(byte[0]);
+            if response.ends_with(b"\r\n\r\n") {
+                Ok(Loop::Break((socket, String::from_utf8_lossy(&response).into_owned())))
+            } else if response.len() > MAX_HTTP_RESPONSE_LEN {
+                Err(NatsError::ProxyError("the response of the proxy is too long".into()))
+            } else {
+                Ok(Loop::Continue((socket, response)))
+            }
+        })
+    })
+}
+
+/// Opens a tunnel following RFC 1928, authenticating following RFC 1929 if credentials are given
+fn socks5_connect(
+    socket: TcpStream,
+    target: &ProxyTarget,
+    credentials: Option<ProxyCredentials>,
+) -> impl Future<Item = TcpStream, Error = NatsError> {
+    let request = match socks5_request(target) {
+        Ok(request) => request,
+        Err(e) => return Either::A(future::err(e)),
+    };
+
+    let methods = if credentials.is_some() {
+        vec![SOCKS5_VERSION, 2, SOCKS5_NO_AUTH, SOCKS5_USER_PASS_AUTH]
+    } else {
+        vec![SOCKS5_VERSION, 1, SOCKS5_NO_AUTH]
+    };
+
+    Either::B(
+        write_all(socket, methods)
+            .from_err()
+            .and_then(|(socket, _)| read_exact(socket, [0u8; 2]).from_err())
+            .and_then(move |(socket, reply)| {
+                if reply[0] != SOCKS5_VERSION {
+                    return Either::A(future::err(NatsError::ProxyError("not a SOCKS5 proxy".into())));
+                }
+
+                match (reply[1], credentials) {
+                    (SOCKS5_NO_AUTH, _) => Either::A(future::ok(socket)),
+                    (SOCKS5_USER_PASS_AUTH, Some(credentials)) => {
+                        Either::B(socks5_authenticate(socket, &credentials))
+                    }
+                    _ => Either::A(future::err(NatsError::ProxyError(
+                        "the proxy doesn't accept any of the authentication methods".into(),
+                    ))),
+                }
+            }).and_then(move |socket| write_all(socket, request).from_err())
+            .and_then(|(socket, _)| read_socks5_reply(socket)),
+    )
+}
+
+fn socks5_authenticate(
+    socket: TcpStream,
+    credentials: &ProxyCredentials,
+) -> impl Future<Item = TcpStream, Error = NatsError> {
+    let (username, password) = (credentials.username.as_bytes(), credentials.password.as_bytes());
+    if username.len() > 255 || password.len() > 255 {
+        return Either::A(future::err(NatsError::ProxyError(
+            "the username and password can't exceed 255 bytes".into(),
+        )));
+    }
+
+    let mut request = vec![1, username.len() as u8];
+    request.extend_from_slice(username);
+    request.push(password.len() as u8);
+    request.extend_from_slice(password);
+
+    Either::B(
+        write_all(socket, request)
+            .from_err()
+            .and_then(|(socket, _)| read_exact(socket, [0u8; 2]).from_err())
+            .and_then(|(socket, reply)| {
+                if reply[1] == 0 {
+                    Ok(socket)
+                } else {
+                    Err(NatsError::ProxyError("the proxy rejected the credentials".into()))
+                }
+            }),
+    )
+}
+
+/// CONNECT request to the target
+fn socks5_request(target: &ProxyTarget) -> Result<Vec<u8>, NatsError> {
+    let mut request = vec![SOCKS5_VERSION, 1, 0];
+    let port = match target {
+        ProxyTarget::Addr(SocketAddr::V4(addr)) => {
+            request.push(1);
+            request.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        }
+        ProxyTarget::Addr(SocketAddr::V6(addr)) => {
+            request.push(4);
+            request.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        }
+        ProxyTarget::Host(host, port) => {
+            if host.len() > 255 {
+                return Err(NatsError::ProxyError(format!("the host {} is too long", host)));
+            }
+
+            request.push(3);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+            *port
+        }
+    };
+
+    request.push((port >> 8) as u8);
+    request.push(port as u8);
+    Ok(request)
+}
+
+/// Reads the reply to the CONNECT request, skipping the address bound by the proxy
+fn read_socks5_reply(socket: TcpStream) -> impl Future<Item = TcpStream, Error = NatsError> {
+    read_exact(socket, [0u8; 4]).from_err().and_then(|(socket, reply)| {
+        if reply[0] != SOCKS5_VERSION || reply[1] != 0 {
+            return Either::A(future::err(NatsError::ProxyError(format!(
+                "the proxy refused the connection (reply code {})",
+                reply[1]
+            ))));
+        }
+
+        let addr_len = match reply[3] {
+            1 => Either::A(future::ok((socket, 4))),
+            4 => Either::A(future::ok((socket, 16))),
+            3 => Either::B(
+                read_exact(socket, [0u8; 1])
+                    .from_err()
+                    .map(|(socket, len)| (socket, len[0] as usize)),
+            ),
+            atyp => Either::A(future::err(NatsError::ProxyError(format!(
+                "unknown address type {} in the reply of the proxy",
+                atyp
+            )))),
+        };
+
+        Either::B(
+            addr_len
+                .and_then(|(socket, len)| read_exact(socket, vec![0u8; len + 2]).from_err())
+                .map(|(socket, _)| socket),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{socks5_request, ProxyTarget};
+
+    #[test]
+    fn it_formats_targets() {
+        assert_eq!(ProxyTarget::Host("nats.internal".into(), 4222).to_string(), "nats.internal:4222");
+        assert_eq!(ProxyTarget::Host("::1".into(), 4222).to_string(), "[::1]:4222");
+        assert_eq!(ProxyTarget::Addr("127.0.0.1:4222".parse().unwrap()).to_string(), "127.0.0.1:4222");
+    }
+
+    #[test]
+    fn it_builds_socks5_requests() {
+        assert_eq!(
+            socks5_request(&ProxyTarget::Addr("127.0.0.1:4222".parse().unwrap())).unwrap(),
+            vec![5, 1, 0, 1, 127, 0, 0, 1, 0x10, 0x7e]
+        );
+        assert_eq!(
+            socks5_request(&ProxyTarget::Host("nats".into(), 4222)).unwrap(),
+            vec![5, 1, 0, 3, 4, b'n', b'a', b't', b's', 0x10, 0x7e]
+        );
+    }
+}
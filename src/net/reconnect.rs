@@ -0,0 +1,242 @@
+use futures::AsyncSink;
+use rand::{thread_rng, Rng};
+use std::{collections::VecDeque, mem, time::Duration};
+
+use error::NatsError;
+use protocol::{
+    commands::{HPubCommand, PubCommand},
+    Op,
+};
+
+/// Policy followed by the client to reconnect when the connection to the server is lost.
+///
+/// Each attempt targets the next server of the pool. The first attempt is immediate, then the delay between
+/// two attempts starts at `initial_delay` and is multiplied by `backoff_multiplier` after each failure, up to
+/// `max_delay`. A random jitter of up to `jitter` is added to every delay to avoid reconnection storms.
+#[derive(Debug, Clone, PartialEq, Builder)]
+#[builder(default, setter(into))]
+pub struct ReconnectPolicy {
+    /// Maximum number of attempts before giving up, `None` retries forever
+    pub max_attempts: Option<u32>,
+    /// Delay before the second attempt
+    pub initial_delay: Duration,
+    /// Upper bound of the delay between two attempts
+    pub max_delay: Duration,
+    /// Factor applied to the delay after each failed attempt, 1 disables the exponential backoff
+    pub backoff_multiplier: u32,
+    /// Upper bound of the random delay added to each wait
+    pub jitter: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            max_attempts: Some(60),
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(5),
+            backoff_multiplier: 2,
+            jitter: Duration::from_millis(100),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    pub fn builder() -> ReconnectPolicyBuilder {
+        ReconnectPolicyBuilder::default()
+    }
+
+    /// Indicates if another attempt can be made after `attempts` failed ones
+    pub(crate) fn should_retry(&self, attempts: u32) -> bool {
+        self.max_attempts.is_none_or(|max| attempts < max)
+    }
+
+    /// Computes the delay to wait before making the given attempt, starting at 0
+    pub(crate) fn delay_for(&self, attempt: u32) -> Duration {
+        if attempt == 0 {
+            return Duration::from_secs(0);
+        }
+
+        let mut delay = self.initial_delay;
+        for _ in 1..attempt {
+            if delay >= self.max_delay {
+                break;
+            }
+
+            delay = delay.checked_mul(self.backoff_multiplier).unwrap_or(self.max_delay);
+        }
+
+        let delay = ::std::cmp::min(delay, self.max_delay);
+        let jitter_ms = self.jitter.as_secs() * 1000 + u64::from(self.jitter.subsec_millis());
+        if jitter_ms == 0 {
+            return delay;
+        }
+
+        delay + Duration::from_millis(thread_rng().gen_range(0, jitter_ms + 1))
+    }
+}
+
+/// Bounded buffer holding the commands issued while the connection is down, flushed in order after reconnecting.
+/// Only publications count towards the limit, the other commands are tiny and needed to keep the server in sync
+#[derive(Debug, Default)]
+pub(crate) struct ReconnectBuffer {
+    ops: VecDeque<Op>,
+    /// Commands the server acknowledges, in the order they were issued: `true` if buffered, `false` if left to the
+    /// replay, which acknowledges them on its own
+    acks: Vec<bool>,
+    /// Bytes currently buffered
+    size: usize,
+    /// Maximum bytes that can be buffered, 0 disables buffering
+    limit: usize,
+}
+
+impl ReconnectBuffer {
+    pub(crate) fn new(limit: usize) -> Self {
+        ReconnectBuffer {
+            ops: VecDeque::new(),
+            acks: vec![],
+            size: 0,
+            limit,
+        }
+    }
+
+    /// Bytes accounted in the buffer for a publication
+    pub(crate) fn pub_size(cmd: &PubCommand) -> usize {
+        cmd.subject.len()
+            + cmd.reply_to.as_ref().map_or(0, |r| r.len())
+            + cmd.headers.as_ref().map_or(0, |h| h.to_bytes().len())
+            + cmd.payload.len()
+    }
+
+    /// Bytes accounted in the buffer for a publication with headers
+    fn hpub_size(cmd: &HPubCommand) -> usize {
+        cmd.subject.len()
+            + cmd.reply_to.as_ref().map_or(0, |r| r.len())
+            + cmd.headers.to_bytes().len()
+            + cmd.payload.len()
+    }
+
+    /// Fails if the buffer can't take the given amount of bytes
+    fn check_room(&self, size: usize) -> Result<(), NatsError> {
+        if self.size + size > self.limit {
+            return Err(NatsError::ReconnectBufferExceeded(self.limit));
+        }
+
+        Ok(())
+    }
+
+    /// Fails if the buffer has no room left for the given publication
+    pub(crate) fn check_room_for(&self, cmd: &PubCommand) -> Result<(), NatsError> {
+        self.check_room(Self::pub_size(cmd))
+    }
+
+    /// Buffers a command, giving it back if there's no room left or if it's not worth replaying. SUBs and CONNECTs
+    /// are left out since the replay sends them again after reconnecting
+    pub(crate) fn push(&mut self, op: Op) -> AsyncSink<Op> {
+        let size = match op {
+            Op::PUB(ref cmd) => Some(Self::pub_size(cmd)).filter(|size| self.check_room(*size).is_ok()),
+            Op::HPUB(ref cmd) => Some(Self::hpub_size(cmd)).filter(|size| self.check_room(*size).is_ok()),
+            Op::SUB(_) | Op::CONNECT(_) => {
+                self.acks.push(false);
+                return AsyncSink::Ready;
+            }
+            ref op if op.is_acknowledged() => Some(0),
+            _ => None,
+        };
+
+        let size = match size {
+            Some(size) => size,
+            None => return AsyncSink::NotReady(op),
+        };
+
+        self.size += size;
+        self.ops.push_back(op);
+        self.acks.push(true);
+        AsyncSink::Ready
+    }
+
+    /// Empties the buffer, returning the commands in the order they were issued
+    pub(crate) fn drain(&mut self) -> Vec<Op> {
+        self.size = 0;
+        self.ops.drain(..).collect()
+    }
+
+    /// Tells which of the acknowledged commands issued since the last call have been buffered, in order
+    pub(crate) fn take_acks(&mut self) -> Vec<bool> {
+        mem::take(&mut self.acks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ReconnectBuffer, ReconnectPolicy};
+    use protocol::{
+        commands::{PubCommand, SubCommand, UnsubCommand},
+        Op,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn it_buffers_up_to_the_limit() {
+        let cmd = PubCommand::builder().subject("foo").payload("bar").build().unwrap();
+        let mut buffer = ReconnectBuffer::new(10);
+        assert!(buffer.push(Op::PUB(cmd.clone())).is_ready());
+        assert!(buffer.check_room_for(&cmd).is_err());
+        assert!(buffer.push(Op::PUB(cmd.clone())).is_not_ready());
+        assert!(buffer.push(Op::PING).is_not_ready());
+        assert!(buffer.push(Op::UNSUB(UnsubCommand::builder().sid("foo").build().unwrap())).is_ready());
+        assert_eq!(buffer.drain().len(), 2);
+        assert!(buffer.check_room_for(&cmd).is_ok());
+    }
+
+    #[test]
+    fn it_leaves_subscriptions_to_the_replay() {
+        let mut buffer = ReconnectBuffer::new(10);
+        let sub = SubCommand::builder().subject("foo").build().unwrap();
+        assert!(buffer.push(Op::SUB(sub)).is_ready());
+        assert!(buffer.push(Op::UNSUB(UnsubCommand::builder().sid("foo").build().unwrap())).is_ready());
+        assert_eq!(buffer.drain().len(), 1);
+        assert_eq!(buffer.take_acks(), vec![false, true]);
+        assert!(buffer.take_acks().is_empty());
+    }
+
+    #[test]
+    fn it_backs_off_exponentially() {
+        let policy = ReconnectPolicy::builder()
+            .initial_delay(Duration::from_millis(100))
+            .max_delay(Duration::from_millis(500))
+            .backoff_multiplier(2u32)
+            .jitter(Duration::from_millis(0))
+            .build()
+            .unwrap();
+
+        assert_eq!(policy.delay_for(0), Duration::from_millis(0));
+        assert_eq!(policy.delay_for(1), Duration::from_millis(100));
+        assert_eq!(policy.delay_for(2), Duration::from_millis(200));
+        assert_eq!(policy.delay_for(3), Duration::from_millis(400));
+        assert_eq!(policy.delay_for(4), Duration::from_millis(500));
+        assert_eq!(policy.delay_for(40), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn it_adds_jitter() {
+        let policy = ReconnectPolicy::builder()
+            .initial_delay(Duration::from_millis(100))
+            .jitter(Duration::from_millis(50))
+            .build()
+            .unwrap();
+
+        let delay = policy.delay_for(1);
+        assert!(delay >= Duration::from_millis(100));
+        assert!(delay <= Duration::from_millis(150));
+    }
+
+    #[test]
+    fn it_gives_up() {
+        let policy = ReconnectPolicy::builder().max_attempts(Some(3)).build().unwrap();
+        assert!(policy.should_retry(2));
+        assert!(!policy.should_retry(3));
+
+        let policy = ReconnectPolicy::builder().max_attempts(None).build().unwrap();
+        assert!(policy.should_retry(u32::MAX));
+    }
+}
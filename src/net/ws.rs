@@ -0,0 +1,148 @@
+use bytes::BytesMut;
+use codec::OpCodec;
+use futures::prelude::*;
+use protocol::Op;
+use std::io::{self, Read, Write};
+use tokio_codec::{Decoder, Encoder};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_tcp::TcpStream;
+use tokio_tungstenite::{
+    tungstenite::{Error as WsError, Message},
+    WebSocketStream,
+};
+
+use error::NatsError;
+use tls::TlsStream;
+
+/// Socket carrying the WebSocket connection, with or without TLS
+#[derive(Debug)]
+pub(crate) enum MaybeTlsStream {
+    Plain(TcpStream),
+    Tls(TlsStream<TcpStream>),
+}
+
+impl Read for MaybeTlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            MaybeTlsStream::Plain(socket) => socket.read(buf),
+            MaybeTlsStream::Tls(socket) => socket.read(buf),
+        }
+    }
+}
+
+impl Write for MaybeTlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            MaybeTlsStream::Plain(socket) => socket.write(buf),
+            MaybeTlsStream::Tls(socket) => socket.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            MaybeTlsStream::Plain(socket) => socket.flush(),
+            MaybeTlsStream::Tls(socket) => socket.flush(),
+        }
+    }
+}
+
+impl AsyncRead for MaybeTlsStream {}
+
+impl AsyncWrite for MaybeTlsStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        match self {
+            MaybeTlsStream::Plain(socket) => AsyncWrite::shutdown(socket),
+            MaybeTlsStream::Tls(socket) => AsyncWrite::shutdown(socket),
+        }
+    }
+}
+
+/// Frames the protocol over a WebSocket connection: each OP sent is a binary message, and the messages received are
+/// decoded as a continuous stream of bytes since the server doesn't align OPs on messages
+pub(crate) struct WsFramed<S> {
+    ws: WebSocketStream<S>,
+    codec: OpCodec,
+    read_buf: BytesMut,
+}
+
+impl<S> WsFramed<S> {
+    pub(crate) fn new(ws: WebSocketStream<S>) -> Self {
+        WsFramed {
+            ws,
+            codec: OpCodec::default(),
+            read_buf: BytesMut::new(),
+        }
+    }
+}
+
+impl<S> ::std::fmt::Debug for WsFramed<S> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        f.debug_struct("WsFramed")
+            .field("codec", &self.codec)
+            .field("read_buf", &self.read_buf)
+            .finish()
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> Stream for WsFramed<S> {
+    type Error = NatsError;
+    type Item = Op;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(op) = self.codec.decode(&mut self.read_buf)? {
+                return Ok(Async::Ready(Some(op)));
+            }
+
+            match self.ws.poll()? {
+                Async::Ready(Some(Message::Binary(data))) => self.read_buf.extend_from_slice(&data),
+                Async::Ready(Some(Message::Text(text))) => self.read_buf.extend_from_slice(text.as_bytes()),
+                // Control messages are handled by tungstenite
+                Async::Ready(Some(_)) => {}
+                Async::Ready(None) => return Ok(Async::Ready(None)),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> Sink for WsFramed<S> {
+    type SinkError = NatsError;
+    type SinkItem = Op;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        // Making room first, since the OP can't be given back once encoded
+        if !self.ws.poll_complete()?.is_ready() {
+            return Ok(AsyncSink::NotReady(item));
+        }
+
+        let mut buf = BytesMut::new();
+        self.codec.encode(item, &mut buf)?;
+        match self.ws.start_send(Message::Binary(buf.to_vec()))? {
+            AsyncSink::Ready => Ok(AsyncSink::Ready),
+            AsyncSink::NotReady(_) => Err(NatsError::WebSocketError("cannot buffer the message".into())),
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        Ok(self.ws.poll_complete()?)
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        Ok(self.ws.close()?)
+    }
+}
+
+impl From<WsError> for NatsError {
+    fn from(err: WsError) -> Self {
+        match err {
+            WsError::Io(e) => NatsError::from(e),
+            // The server went away, with or without a closing handshake, which calls for a reconnection
+            WsError::ConnectionClosed | WsError::AlreadyClosed => NatsError::ServerDisconnected(None),
+            WsError::Protocol(ref reason) if reason == "Connection reset without closing handshake" => {
+                NatsError::ServerDisconnected(None)
+            }
+            e => NatsError::WebSocketError(e.to_string()),
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use bytes::Bytes;
-use protocol::{Command, CommandError};
+use protocol::{commands::ServerInfo, Command, CommandError};
 use serde_json as json;
 
 /// The CONNECT message is the client version of the INFO message. Once the client has established a TCP/IP
@@ -24,6 +24,16 @@ pub struct ConnectCommand {
     /// Connection password (if auth_required is set)
     #[serde(skip_serializing_if = "Option::is_none")]
     pass: Option<String>,
+    /// The public NKey of the user, for NKey authentication (if auth_required is set)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nkey: Option<String>,
+    /// The user JWT, for decentralized authentication (if auth_required is set)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    jwt: Option<String>,
+    /// The nonce sent by the server in its INFO, signed with the NKey of the user and encoded in URL-safe base64
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(skip))]
+    sig: Option<String>,
     /// Optional client name
     #[serde(skip_serializing_if = "Option::is_none")]
     #[builder(default = "self.default_name()?")]
@@ -44,12 +54,64 @@ pub struct ConnectCommand {
     /// which is when proto in the INFO protocol is set to at least 1.
     #[serde(skip_serializing_if = "Option::is_none")]
     echo: Option<bool>,
+    /// Optional boolean. Tells the server that the client supports headers, which is only sent to servers
+    /// advertising support for them in their INFO.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(skip))]
+    headers: Option<bool>,
+    /// Optional boolean. Asks the server to reply to requests nobody listens to with a 503 status right away,
+    /// which requires headers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(skip))]
+    no_responders: Option<bool>,
 }
 
 impl ConnectCommand {
     pub fn builder() -> ConnectCommandBuilder {
         ConnectCommandBuilder::default()
     }
+
+    /// Indicates if the command carries credentials, as required by servers with `auth_required` set
+    pub(crate) fn has_credentials(&self) -> bool {
+        self.auth_token.is_some() || self.user.is_some() || self.nkey.is_some() || self.jwt.is_some()
+    }
+
+    /// Replaces the token and user/password credentials, with the ones given in the URL of the server
+    pub(crate) fn set_credentials(&mut self, auth_token: Option<String>, user: Option<String>, pass: Option<String>) {
+        self.auth_token = auth_token;
+        self.user = user;
+        self.pass = pass;
+    }
+
+    /// Sets the NKey credentials along with the signature of the server nonce
+    pub(crate) fn set_signature(&mut self, nkey: Option<String>, jwt: Option<String>, sig: String) {
+        if nkey.is_some() {
+            self.nkey = nkey;
+        }
+
+        if jwt.is_some() {
+            self.jwt = jwt;
+        }
+
+        self.sig = Some(sig);
+    }
+
+    /// Adapts the command to what the server told about itself in its INFO: TLS is required if the server requires
+    /// it, `echo`/`protocol` are only sent to servers supporting them (`proto` >= 1) and headers, along with the
+    /// no-responders status that relies on them, are enabled when the server supports them
+    pub(crate) fn for_server(&self, server_info: &ServerInfo) -> ConnectCommand {
+        let mut cmd = self.clone();
+        cmd.tls_required = cmd.tls_required || server_info.tls_required.unwrap_or(false);
+        if server_info.proto.unwrap_or(0) < 1 {
+            cmd.echo = None;
+            cmd.protocol = None;
+        }
+
+        cmd.headers = if server_info.headers == Some(true) { Some(true) } else { None };
+        cmd.no_responders = cmd.headers;
+
+        cmd
+    }
 }
 
 impl ConnectCommandBuilder {
@@ -94,7 +156,7 @@ impl Command for ConnectCommand {
 #[cfg(test)]
 mod tests {
     use super::{ConnectCommand, ConnectCommandBuilder};
-    use protocol::Command;
+    use protocol::{commands::ServerInfo, Command};
 
     static DEFAULT_CONNECT: &'static str = "CONNECT\t{\"verbose\":false,\"pedantic\":false,\"tls_required\":false,\"name\":\"nitox\",\"lang\":\"rust\",\"version\":\"1.0.0\"}\r\n";
 
@@ -127,4 +189,39 @@ mod tests {
 
         assert_eq!(DEFAULT_CONNECT, cmd_bytes);
     }
+
+    #[test]
+    fn it_adapts_to_the_server() {
+        let cmd = ConnectCommandBuilder::default()
+            .echo(Some(false))
+            .protocol(Some(1))
+            .build()
+            .unwrap();
+
+        let mut info = ServerInfo::builder()
+            .server_id("nitox")
+            .version("1.0.0")
+            .go("go1.11")
+            .host("127.0.0.1")
+            .port(4222u32)
+            .max_payload(1024u32)
+            .tls_required(Some(true))
+            .build()
+            .unwrap();
+
+        let adapted = cmd.for_server(&info);
+        assert!(adapted.tls_required);
+        assert_eq!(adapted.echo, None);
+        assert_eq!(adapted.protocol, None);
+        assert_eq!(adapted.headers, None);
+        assert_eq!(adapted.no_responders, None);
+
+        info.proto = Some(1);
+        info.headers = Some(true);
+        let adapted = cmd.for_server(&info);
+        assert_eq!(adapted.echo, Some(false));
+        assert_eq!(adapted.protocol, Some(1));
+        assert_eq!(adapted.headers, Some(true));
+        assert_eq!(adapted.no_responders, Some(true));
+    }
 }
@@ -0,0 +1,164 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use protocol::{commands::PubCommand, Command, CommandError, HeaderMap};
+
+/// The HPUB message is the same as PUB but carries headers along with the payload. It requires a server supporting
+/// headers (NATS 2.2+), as advertised in its INFO.
+#[derive(Debug, Clone, PartialEq, Builder)]
+#[builder(build_fn(validate = "Self::validate"))]
+pub struct HPubCommand {
+    /// The destination subject to publish to
+    #[builder(setter(into))]
+    pub subject: String,
+    /// The optional reply inbox subject that subscribers can use to send a response back to the publisher/requestor
+    #[builder(default)]
+    pub reply_to: Option<String>,
+    /// The headers of the message
+    #[builder(default)]
+    pub headers: HeaderMap,
+    /// The message payload data
+    #[builder(default, setter(into))]
+    pub payload: Bytes,
+}
+
+impl HPubCommand {
+    pub fn builder() -> HPubCommandBuilder {
+        HPubCommandBuilder::default()
+    }
+}
+
+impl From<PubCommand> for HPubCommand {
+    fn from(cmd: PubCommand) -> Self {
+        HPubCommand {
+            subject: cmd.subject,
+            reply_to: cmd.reply_to,
+            headers: cmd.headers.unwrap_or_default(),
+            payload: cmd.payload,
+        }
+    }
+}
+
+impl Command for HPubCommand {
+    const CMD_NAME: &'static [u8] = b"HPUB";
+
+    fn into_vec(self) -> Result<Bytes, CommandError> {
+        let rt = if let Some(reply_to) = self.reply_to {
+            format!("\t{}", reply_to)
+        } else {
+            "".into()
+        };
+
+        let headers = self.headers.to_bytes();
+        let total_len = headers.len() + self.payload.len();
+        let cmd_str = format!("HPUB\t{}{}\t{}\t{}\r\n", self.subject, rt, headers.len(), total_len);
+        let mut bytes = BytesMut::with_capacity(cmd_str.len() + total_len + 2);
+        bytes.put(cmd_str.as_bytes());
+        bytes.put(headers);
+        bytes.put(self.payload);
+        bytes.put("\r\n");
+
+        Ok(bytes.freeze())
+    }
+
+    fn try_parse(buf: &[u8]) -> Result<Self, CommandError> {
+        let len = buf.len();
+
+        if buf[len - 2..] != [b'\r', b'\n'] {
+            return Err(CommandError::IncompleteCommandError);
+        }
+
+        if let Some(body_start) = buf[..len - 2].windows(2).position(|w| w == b"\r\n") {
+            let body = &buf[body_start + 2..len - 2];
+
+            let whole_command = ::std::str::from_utf8(&buf[..body_start])?;
+            let mut split = whole_command.split_whitespace();
+            let cmd = split.next().ok_or(CommandError::CommandMalformed)?;
+            // Check if we're still on the right command
+            if cmd.as_bytes() != Self::CMD_NAME {
+                return Err(CommandError::CommandMalformed);
+            }
+
+            let total_len: usize = split
+                .next_back()
+                .ok_or(CommandError::CommandMalformed)?
+                .parse()?;
+
+            let headers_len: usize = split
+                .next_back()
+                .ok_or(CommandError::CommandMalformed)?
+                .parse()?;
+
+            if body.len() != total_len || headers_len > total_len {
+                return Err(CommandError::CommandMalformed);
+            }
+
+            // Extract subject
+            let subject: String = split.next().ok_or(CommandError::CommandMalformed)?.into();
+
+            let reply_to: Option<String> = split.next().map(|v| v.into());
+
+            Ok(HPubCommand {
+                subject,
+                reply_to,
+                headers: HeaderMap::parse(&body[..headers_len])?,
+                payload: body[headers_len..].into(),
+            })
+        } else {
+            Err(CommandError::CommandMalformed)
+        }
+    }
+}
+
+impl HPubCommandBuilder {
+    fn validate(&self) -> Result<(), String> {
+        if let Some(ref subj) = self.subject {
+            check_cmd_arg!(subj, "subject");
+        }
+
+        if let Some(Some(ref reply_to)) = self.reply_to {
+            check_cmd_arg!(reply_to, "inbox");
+        }
+
+        if let Some(ref headers) = self.headers {
+            headers.validate()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HPubCommand, HPubCommandBuilder};
+    use protocol::{Command, HeaderMap};
+
+    static DEFAULT_HPUB: &str = "HPUB\tFOO\t22\t33\r\nNATS/1.0\r\nBar: baz\r\n\r\nHello NATS!\r\n";
+
+    #[test]
+    fn it_parses() {
+        let parse_res = HPubCommand::try_parse(DEFAULT_HPUB.as_bytes());
+        assert!(parse_res.is_ok());
+        let cmd = parse_res.unwrap();
+        assert_eq!(&cmd.subject, "FOO");
+        assert_eq!(&cmd.payload, "Hello NATS!");
+        assert_eq!(cmd.headers.get("Bar"), Some("baz"));
+        assert!(cmd.reply_to.is_none());
+    }
+
+    #[test]
+    fn it_stringifies() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Bar", "baz");
+        let cmd = HPubCommandBuilder::default()
+            .subject("FOO")
+            .headers(headers)
+            .payload("Hello NATS!")
+            .build()
+            .unwrap();
+
+        let cmd_bytes_res = cmd.into_vec();
+        assert!(cmd_bytes_res.is_ok());
+        let cmd_bytes = cmd_bytes_res.unwrap();
+
+        assert_eq!(DEFAULT_HPUB, cmd_bytes);
+    }
+}
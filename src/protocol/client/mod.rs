@@ -1,4 +1,5 @@
 pub mod connect;
+pub mod hpub_cmd;
 pub mod pub_cmd;
 pub mod sub_cmd;
 pub mod unsub_cmd;
@@ -1,5 +1,5 @@
 use bytes::{BufMut, Bytes, BytesMut};
-use protocol::{Command, CommandError};
+use protocol::{Command, CommandError, HeaderMap, Op};
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 
 /// The PUB message publishes the message payload to the given subject name, optionally supplying a reply subject.
@@ -14,6 +14,10 @@ pub struct PubCommand {
     /// The optional reply inbox subject that subscribers can use to send a response back to the publisher/requestor
     #[builder(default)]
     pub reply_to: Option<String>,
+    /// The optional headers of the message. The client sends the message through HPUB when they're set, which
+    /// requires a server supporting headers
+    #[builder(default)]
+    pub headers: Option<HeaderMap>,
     /// The message payload data
     #[builder(default, setter(into))]
     pub payload: Bytes,
@@ -24,6 +28,20 @@ impl PubCommand {
         PubCommandBuilder::default()
     }
 
+    /// Wraps the command in the OP matching it: HPUB when it has headers, PUB otherwise
+    pub(crate) fn into_op(self) -> Op {
+        if self.headers.is_some() {
+            Op::HPUB(self.into())
+        } else {
+            Op::PUB(self)
+        }
+    }
+
+    /// Size of the headers and payload, as checked against the `max_payload` of the server
+    pub(crate) fn total_len(&self) -> usize {
+        self.headers.as_ref().map_or(0, |h| h.to_bytes().len()) + self.payload.len()
+    }
+
     /// Generates a random `reply_to` `String`
     pub fn generate_reply_to() -> String {
         let mut rng = thread_rng();
@@ -90,6 +108,7 @@ impl Command for PubCommand {
                 subject,
                 payload,
                 reply_to,
+                headers: None,
             })
         } else {
             Err(CommandError::CommandMalformed)
@@ -115,6 +134,10 @@ impl PubCommandBuilder {
             }
         }
 
+        if let Some(Some(ref headers)) = self.headers {
+            headers.validate()?;
+        }
+
         Ok(())
     }
 }
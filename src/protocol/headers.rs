@@ -0,0 +1,195 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use protocol::CommandError;
+use std::collections::BTreeMap;
+
+const HEADER_LINE: &str = "NATS/1.0";
+
+/// Headers carried by HPUB and HMSG messages. Keys are case-preserving and can hold multiple values, and the
+/// `NATS/1.0` status line can carry an inline status code with its description (e.g. `503` for no responders)
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct HeaderMap {
+    status: Option<u16>,
+    description: Option<String>,
+    entries: BTreeMap<String, Vec<String>>,
+}
+
+impl HeaderMap {
+    pub fn new() -> Self {
+        HeaderMap::default()
+    }
+
+    /// Sets the value of a key, replacing the existing ones
+    pub fn insert<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+        self.entries.insert(key.into(), vec![value.into()]);
+    }
+
+    /// Adds a value to a key, keeping the existing ones
+    pub fn append<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+        self.entries.entry(key.into()).or_default().push(value.into());
+    }
+
+    /// First value of a key
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.get(key).and_then(|values| values.first()).map(|v| v.as_str())
+    }
+
+    /// All the values of a key
+    pub fn get_all(&self, key: &str) -> &[String] {
+        self.entries.get(key).map_or(&[], |values| values.as_slice())
+    }
+
+    /// Removes a key, returning its values
+    pub fn remove(&mut self, key: &str) -> Option<Vec<String>> {
+        self.entries.remove(key)
+    }
+
+    /// Iterates over the keys and their values
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[String])> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v.as_slice()))
+    }
+
+    /// Number of keys
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty() && self.status.is_none()
+    }
+
+    /// Status code of the status line, if any
+    pub fn status(&self) -> Option<u16> {
+        self.status
+    }
+
+    /// Description following the status code, if any
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// Sets the status line
+    pub fn set_status(&mut self, status: u16, description: Option<String>) {
+        self.status = Some(status);
+        self.description = description;
+    }
+
+    /// Checks that the headers can be put on the wire: keys can't contain colons or whitespaces and nothing can
+    /// contain line breaks
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        for (key, values) in &self.entries {
+            if key.is_empty() || key.contains(|c: char| c == ':' || c.is_whitespace()) {
+                return Err(format!("header key {:?} is invalid", key));
+            }
+
+            if values.iter().any(|v| v.contains('\r') || v.contains('\n')) {
+                return Err(format!("header {:?} contains line breaks", key));
+            }
+        }
+
+        if let Some(ref description) = self.description {
+            if description.contains('\r') || description.contains('\n') {
+                return Err("header status description contains line breaks".into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Encodes the headers, including the blank line ending them
+    pub(crate) fn to_bytes(&self) -> Bytes {
+        let mut status_line = HEADER_LINE.to_string();
+        if let Some(status) = self.status {
+            status_line.push_str(&format!(" {}", status));
+            if let Some(ref description) = self.description {
+                status_line.push(' ');
+                status_line.push_str(description);
+            }
+        }
+
+        let mut bytes = BytesMut::with_capacity(status_line.len() + 4);
+        bytes.put(status_line.as_bytes());
+        bytes.put("\r\n");
+        for (key, values) in &self.entries {
+            for value in values {
+                let line = format!("{}: {}\r\n", key, value);
+                bytes.reserve(line.len());
+                bytes.put(line.as_bytes());
+            }
+        }
+
+        bytes.reserve(2);
+        bytes.put("\r\n");
+        bytes.freeze()
+    }
+
+    /// Parses the headers section of an HPUB/HMSG message
+    pub(crate) fn parse(buf: &[u8]) -> Result<Self, CommandError> {
+        let headers = ::std::str::from_utf8(buf)?;
+        let mut lines = headers.split("\r\n");
+        let status_line = lines.next().ok_or(CommandError::CommandMalformed)?;
+        if !status_line.starts_with(HEADER_LINE) {
+            return Err(CommandError::CommandMalformed);
+        }
+
+        let mut map = HeaderMap::default();
+        let status = status_line[HEADER_LINE.len()..].trim();
+        if !status.is_empty() {
+            let mut split = status.splitn(2, ' ');
+            map.status = Some(split.next().ok_or(CommandError::CommandMalformed)?.parse()?);
+            map.description = split.next().map(|d| d.trim().to_string()).filter(|d| !d.is_empty());
+        }
+
+        for line in lines.take_while(|line| !line.is_empty()) {
+            let idx = line.find(':').ok_or(CommandError::CommandMalformed)?;
+            map.append(&line[..idx], line[idx + 1..].trim());
+        }
+
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HeaderMap;
+
+    static HEADERS: &str = "NATS/1.0\r\nFoo: bar\r\nFoo: baz\r\ntrace-id: 42\r\n\r\n";
+
+    #[test]
+    fn it_parses() {
+        let headers = HeaderMap::parse(HEADERS.as_bytes()).unwrap();
+        assert_eq!(headers.get("Foo"), Some("bar"));
+        assert_eq!(headers.get_all("Foo"), &["bar".to_string(), "baz".to_string()]);
+        assert_eq!(headers.get("foo"), None);
+        assert_eq!(headers.get("trace-id"), Some("42"));
+        assert_eq!(headers.status(), None);
+    }
+
+    #[test]
+    fn it_parses_inline_status() {
+        let headers = HeaderMap::parse(b"NATS/1.0 503\r\n\r\n").unwrap();
+        assert_eq!(headers.status(), Some(503));
+        assert_eq!(headers.description(), None);
+
+        let headers = HeaderMap::parse(b"NATS/1.0 404 No Messages\r\n\r\n").unwrap();
+        assert_eq!(headers.status(), Some(404));
+        assert_eq!(headers.description(), Some("No Messages"));
+    }
+
+    #[test]
+    fn it_stringifies() {
+        let mut headers = HeaderMap::new();
+        headers.append("Foo", "bar");
+        headers.append("Foo", "baz");
+        headers.insert("trace-id", "42");
+        assert_eq!(headers.to_bytes(), HEADERS);
+    }
+
+    #[test]
+    fn it_validates() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Foo", "bar");
+        assert!(headers.validate().is_ok());
+        headers.insert("Foo Bar", "baz");
+        assert!(headers.validate().is_err());
+    }
+}
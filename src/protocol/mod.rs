@@ -42,15 +42,18 @@ mod error;
 pub use self::error::*;
 
 mod client;
+mod headers;
 mod server;
+pub use self::headers::HeaderMap;
 
 mod op;
 pub use self::op::*;
 
 pub mod commands {
     pub use super::{
-        client::{connect::*, pub_cmd::*, sub_cmd::*, unsub_cmd::*},
-        server::{info::*, message::*, server_error::ServerError},
+        client::{connect::*, hpub_cmd::*, pub_cmd::*, sub_cmd::*, unsub_cmd::*},
+        server::{hmessage::*, info::*, message::*, server_error::ServerError},
+        HeaderMap,
     };
     pub use Command;
 }
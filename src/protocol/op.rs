@@ -10,12 +10,16 @@ pub enum Op {
     CONNECT(ConnectCommand),
     /// **CLIENT** Publish a message to a subject, with optional reply subject
     PUB(PubCommand),
+    /// **CLIENT** Publish a message with headers to a subject, with optional reply subject
+    HPUB(HPubCommand),
     /// **CLIENT** Subscribe to a subject (or subject wildcard)
     SUB(SubCommand),
     /// **CLIENT** Unsubscribe (or auto-unsubscribe) from subject
     UNSUB(UnsubCommand),
     /// **SERVER** Delivers a message payload to a subscriber
     MSG(Message),
+    /// **SERVER** Delivers a message payload with headers to a subscriber
+    HMSG(HMessage),
     /// **BOTH** PING keep-alive message
     PING,
     /// **BOTH** PONG keep-alive message
@@ -39,15 +43,22 @@ macro_rules! op_from_cmd {
 }
 
 impl Op {
+    /// Indicates if the server acknowledges the OP with a `+OK` (or rejects it with a `-ERR`) in `verbose` mode
+    pub(crate) fn is_acknowledged(&self) -> bool {
+        matches!(self, Op::CONNECT(_) | Op::PUB(_) | Op::HPUB(_) | Op::SUB(_) | Op::UNSUB(_))
+    }
+
     /// Transforms the OP into a byte slice
     pub fn into_bytes(self) -> Result<Bytes, CommandError> {
         Ok(match self {
             Op::INFO(si) => si.into_vec()?,
             Op::CONNECT(con) => con.into_vec()?,
             Op::PUB(pc) => pc.into_vec()?,
+            Op::HPUB(hpc) => hpc.into_vec()?,
             Op::SUB(sc) => sc.into_vec()?,
             Op::UNSUB(uc) => uc.into_vec()?,
             Op::MSG(msg) => msg.into_vec()?,
+            Op::HMSG(hmsg) => hmsg.into_vec()?,
             Op::PING => "PING\r\n".into(),
             Op::PONG => "PONG\r\n".into(),
             Op::OK => "+OK\r\n".into(),
@@ -61,7 +72,9 @@ impl Op {
             ServerInfo::CMD_NAME => op_from_cmd!(buf, ServerInfo::try_parse, Op::INFO),
             ConnectCommand::CMD_NAME => op_from_cmd!(buf, ConnectCommand::try_parse, Op::CONNECT),
             Message::CMD_NAME => op_from_cmd!(buf, Message::try_parse, Op::MSG),
+            HMessage::CMD_NAME => op_from_cmd!(buf, HMessage::try_parse, Op::HMSG),
             PubCommand::CMD_NAME => op_from_cmd!(buf, PubCommand::try_parse, Op::PUB),
+            HPubCommand::CMD_NAME => op_from_cmd!(buf, HPubCommand::try_parse, Op::HPUB),
             SubCommand::CMD_NAME => op_from_cmd!(buf, SubCommand::try_parse, Op::SUB),
             UnsubCommand::CMD_NAME => op_from_cmd!(buf, UnsubCommand::try_parse, Op::UNSUB),
             b"PING" => {
@@ -86,8 +99,10 @@ impl Op {
                 }
             }
             b"-ERR" => {
-                if &buf[buf.len() - 2..] == b"\r\n" {
-                    Ok(Op::ERR(ServerError::from(String::from_utf8(buf[1..].to_vec())?)))
+                let len = buf.len();
+                if &buf[len - 2..] == b"\r\n" {
+                    // Keeping only the message, in between "-ERR " and the CRLF
+                    Ok(Op::ERR(ServerError::from(String::from_utf8(buf[4..len - 2].to_vec())?)))
                 } else {
                     Err(CommandError::IncompleteCommandError)
                 }
@@ -0,0 +1,179 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use protocol::{commands::Message, Command, CommandError, HeaderMap};
+
+/// The HMSG protocol message is the same as MSG but delivers headers along with the payload.
+#[derive(Debug, Clone, PartialEq, Builder)]
+#[builder(build_fn(validate = "Self::validate"))]
+pub struct HMessage {
+    /// Subject name this message was received on
+    #[builder(setter(into))]
+    pub subject: String,
+    /// The unique alphanumeric subscription ID of the subject
+    #[builder(setter(into))]
+    pub sid: String,
+    /// The inbox subject on which the publisher is listening for responses
+    #[builder(default)]
+    pub reply_to: Option<String>,
+    /// The headers of the message
+    #[builder(default)]
+    pub headers: HeaderMap,
+    /// The message payload data
+    #[builder(setter(into))]
+    pub payload: Bytes,
+}
+
+impl HMessage {
+    pub fn builder() -> HMessageBuilder {
+        HMessageBuilder::default()
+    }
+}
+
+impl From<HMessage> for Message {
+    fn from(msg: HMessage) -> Self {
+        Message {
+            subject: msg.subject,
+            sid: msg.sid,
+            reply_to: msg.reply_to,
+            headers: Some(msg.headers),
+            payload: msg.payload,
+        }
+    }
+}
+
+impl Command for HMessage {
+    const CMD_NAME: &'static [u8] = b"HMSG";
+
+    fn into_vec(self) -> Result<Bytes, CommandError> {
+        let rt = if let Some(reply_to) = self.reply_to {
+            format!("\t{}", reply_to)
+        } else {
+            "".into()
+        };
+
+        let headers = self.headers.to_bytes();
+        let total_len = headers.len() + self.payload.len();
+        let cmd_str = format!(
+            "HMSG\t{}\t{}{}\t{}\t{}\r\n",
+            self.subject,
+            self.sid,
+            rt,
+            headers.len(),
+            total_len
+        );
+        let mut bytes = BytesMut::with_capacity(cmd_str.len() + total_len + 2);
+        bytes.put(cmd_str.as_bytes());
+        bytes.put(headers);
+        bytes.put(self.payload);
+        bytes.put("\r\n");
+
+        Ok(bytes.freeze())
+    }
+
+    fn try_parse(buf: &[u8]) -> Result<Self, CommandError> {
+        let len = buf.len();
+
+        if buf[len - 2..] != [b'\r', b'\n'] {
+            return Err(CommandError::IncompleteCommandError);
+        }
+
+        if let Some(body_start) = buf[..len - 2].windows(2).position(|w| w == b"\r\n") {
+            let body = &buf[body_start + 2..len - 2];
+
+            let whole_command = ::std::str::from_utf8(&buf[..body_start])?;
+            let mut split = whole_command.split_whitespace();
+            let cmd = split.next().ok_or(CommandError::CommandMalformed)?;
+            // Check if we're still on the right command
+            if cmd.as_bytes() != Self::CMD_NAME {
+                return Err(CommandError::CommandMalformed);
+            }
+
+            let total_len: usize = split
+                .next_back()
+                .ok_or(CommandError::CommandMalformed)?
+                .parse()?;
+
+            let headers_len: usize = split
+                .next_back()
+                .ok_or(CommandError::CommandMalformed)?
+                .parse()?;
+
+            if body.len() != total_len || headers_len > total_len {
+                return Err(CommandError::CommandMalformed);
+            }
+
+            // Extract subject
+            let subject: String = split.next().ok_or(CommandError::CommandMalformed)?.into();
+
+            let sid: String = split.next().ok_or(CommandError::CommandMalformed)?.into();
+
+            let reply_to: Option<String> = split.next().map(|v| v.into());
+
+            Ok(HMessage {
+                subject,
+                sid,
+                reply_to,
+                headers: HeaderMap::parse(&body[..headers_len])?,
+                payload: body[headers_len..].into(),
+            })
+        } else {
+            Err(CommandError::CommandMalformed)
+        }
+    }
+}
+
+impl HMessageBuilder {
+    fn validate(&self) -> Result<(), String> {
+        if let Some(ref subj) = self.subject {
+            check_cmd_arg!(subj, "subject");
+        }
+
+        if let Some(Some(ref reply_to)) = self.reply_to {
+            check_cmd_arg!(reply_to, "inbox");
+        }
+
+        if let Some(ref headers) = self.headers {
+            headers.validate()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HMessage, HMessageBuilder};
+    use protocol::{Command, HeaderMap};
+
+    static DEFAULT_HMSG: &str = "HMSG\tFOO\tpouet\t16\t20\r\nNATS/1.0 503\r\n\r\ntoto\r\n";
+
+    #[test]
+    fn it_parses() {
+        let parse_res = HMessage::try_parse(DEFAULT_HMSG.as_bytes());
+        assert!(parse_res.is_ok());
+        let cmd = parse_res.unwrap();
+        assert!(cmd.reply_to.is_none());
+        assert_eq!(&cmd.subject, "FOO");
+        assert_eq!(&cmd.sid, "pouet");
+        assert_eq!(cmd.headers.status(), Some(503));
+        assert_eq!(cmd.payload, "toto");
+    }
+
+    #[test]
+    fn it_stringifies() {
+        let mut headers = HeaderMap::new();
+        headers.set_status(503, None);
+        let cmd = HMessageBuilder::default()
+            .subject("FOO")
+            .sid("pouet")
+            .headers(headers)
+            .payload("toto")
+            .build()
+            .unwrap();
+
+        let cmd_bytes_res = cmd.into_vec();
+        assert!(cmd_bytes_res.is_ok());
+        let cmd_bytes = cmd_bytes_res.unwrap();
+
+        assert_eq!(DEFAULT_HMSG, cmd_bytes);
+    }
+}
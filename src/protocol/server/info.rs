@@ -56,6 +56,20 @@ pub struct ServerInfo {
     #[builder(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) connect_urls: Option<Vec<String>>,
+    /// If this is set, the server is in lame duck mode: it will soon shut down and the client should reconnect
+    /// to another server of the cluster.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) ldm: Option<bool>,
+    /// If this is set, the server supports headers (HPUB/HMSG)
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) headers: Option<bool>,
+    /// If this is set, the server expects the client to sign this nonce with its NKey and send the signature in the
+    /// `sig` field of its CONNECT.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) nonce: Option<String>,
 }
 
 impl ServerInfo {
@@ -1,5 +1,5 @@
 use bytes::{BufMut, Bytes, BytesMut};
-use protocol::{Command, CommandError};
+use protocol::{Command, CommandError, HeaderMap};
 
 /// The MSG protocol message is used to deliver an application message to the client.
 #[derive(Debug, Clone, PartialEq, Builder)]
@@ -14,6 +14,9 @@ pub struct Message {
     /// The inbox subject on which the publisher is listening for responses
     #[builder(default)]
     pub reply_to: Option<String>,
+    /// The headers of the message, only set when it has been delivered through HMSG
+    #[builder(default)]
+    pub headers: Option<HeaderMap>,
     /// The message payload data
     #[builder(setter(into))]
     pub payload: Bytes,
@@ -23,6 +26,11 @@ impl Message {
     pub fn builder() -> MessageBuilder {
         MessageBuilder::default()
     }
+
+    /// Indicates if this is the 503 status the server replies with when nobody listens to the subject of a request
+    pub fn is_no_responders(&self) -> bool {
+        self.payload.is_empty() && self.headers.as_ref().and_then(|headers| headers.status()) == Some(503)
+    }
 }
 
 impl Command for Message {
@@ -87,6 +95,7 @@ impl Command for Message {
                 sid,
                 payload,
                 reply_to,
+                headers: None,
             })
         } else {
             Err(CommandError::CommandMalformed)
@@ -113,7 +122,7 @@ impl MessageBuilder {
 #[cfg(test)]
 mod tests {
     use super::{Message, MessageBuilder};
-    use protocol::Command;
+    use protocol::{Command, HeaderMap};
 
     static DEFAULT_MSG: &'static str = "MSG\tFOO\tpouet\t4\r\ntoto\r\n";
 
@@ -143,4 +152,23 @@ mod tests {
 
         assert_eq!(DEFAULT_MSG, cmd_bytes);
     }
+
+    #[test]
+    fn it_detects_no_responders() {
+        let mut headers = HeaderMap::new();
+        headers.set_status(503, None);
+        let mut msg = MessageBuilder::default()
+            .subject("_INBOX.foo")
+            .sid("pouet")
+            .headers(Some(headers))
+            .payload("")
+            .build()
+            .unwrap();
+        assert!(msg.is_no_responders());
+
+        msg.payload = "toto".into();
+        assert!(!msg.is_no_responders());
+        msg.headers = None;
+        assert!(!msg.is_no_responders());
+    }
 }
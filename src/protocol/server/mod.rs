@@ -1,3 +1,4 @@
+pub mod hmessage;
 pub mod info;
 pub mod message;
 pub mod server_error;
@@ -5,15 +5,201 @@ use std::fmt;
 ///
 /// Handling of these errors usually has to be done asynchronously.
 #[derive(Debug, PartialEq, Clone)]
-pub struct ServerError(String);
+pub enum ServerError {
+    /// Unknown protocol error
+    UnknownProtocolOperation,
+    /// Client attempted to connect to a route port instead of the client port
+    AttemptedToConnectToRoutePort,
+    /// Client failed to authenticate to the server with credentials specified in the CONNECT message
+    AuthorizationViolation,
+    /// Client took too long to authenticate to the server after establishing a connection
+    AuthenticationTimeout,
+    /// Client specified an invalid protocol version in the CONNECT message
+    InvalidClientProtocol,
+    /// Message destination subject and reply subject length exceeded the maximum control line value
+    MaximumControlLineExceeded,
+    /// Cannot parse the protocol message sent by the client
+    ParserError,
+    /// The server requires TLS and the client does not have TLS enabled
+    SecureConnectionTlsRequired,
+    /// The server hasn't received a message from the client, including a PONG in too long
+    StaleConnection,
+    /// This error is sent by the server when creating a new connection and the server has exceeded the maximum
+    /// number of connections specified by the `max_connections` server configuration
+    MaximumConnectionsExceeded,
+    /// The server pending data size for the connection has reached the maximum size
+    SlowConsumer,
+    /// Client attempted to publish a message with a payload size that exceeds the `max_payload` size configured on
+    /// the server
+    MaximumPayloadViolation,
+    /// Client sent a malformed subject
+    InvalidSubject,
+    /// The user specified in the CONNECT message does not have permission to subscribe to the subject, contains the
+    /// subject and the queue group if any
+    SubscriptionPermissionsViolation(String, Option<String>),
+    /// The user specified in the CONNECT message does not have permissions to publish to the subject, contains the
+    /// subject
+    PublishPermissionsViolation(String),
+    /// Any other error, contains the message sent by the server
+    Other(String),
+}
+
+const SUB_VIOLATION_PREFIX: &str = "Permissions Violation for Subscription to ";
+const PUB_VIOLATION_PREFIX: &str = "Permissions Violation for Publish to ";
+const QUEUE_SEPARATOR: &str = " using queue ";
+
+impl ServerError {
+    /// Indicates if the server closes the connection after sending this error
+    pub fn is_fatal(&self) -> bool {
+        !matches!(
+            self,
+            ServerError::InvalidSubject
+                | ServerError::SubscriptionPermissionsViolation(..)
+                | ServerError::PublishPermissionsViolation(_)
+                | ServerError::Other(_)
+        )
+    }
+
+    /// Indicates if the error is sent in place of the +OK of the command that caused it, in verbose mode. The other
+    /// errors are unsolicited, and publications are acknowledged before being checked against the permissions
+    pub(crate) fn replaces_ack(&self) -> bool {
+        matches!(
+            self,
+            ServerError::UnknownProtocolOperation
+                | ServerError::InvalidSubject
+                | ServerError::ParserError
+                | ServerError::MaximumControlLineExceeded
+                | ServerError::MaximumPayloadViolation
+                | ServerError::SubscriptionPermissionsViolation(..)
+        )
+    }
+}
+
+impl<'a> From<&'a str> for ServerError {
+    fn from(s: &'a str) -> Self {
+        let message = s.trim().trim_matches('\'');
+        match message.to_lowercase().as_str() {
+            "unknown protocol operation" => return ServerError::UnknownProtocolOperation,
+            "attempted to connect to route port" => return ServerError::AttemptedToConnectToRoutePort,
+            "authorization violation" => return ServerError::AuthorizationViolation,
+            "authentication timeout" | "authorization timeout" => return ServerError::AuthenticationTimeout,
+            "invalid client protocol" => return ServerError::InvalidClientProtocol,
+            "maximum control line exceeded" => return ServerError::MaximumControlLineExceeded,
+            "parser error" => return ServerError::ParserError,
+            "secure connection - tls required" => return ServerError::SecureConnectionTlsRequired,
+            "stale connection" => return ServerError::StaleConnection,
+            "maximum connections exceeded" => return ServerError::MaximumConnectionsExceeded,
+            "slow consumer" => return ServerError::SlowConsumer,
+            "maximum payload violation" => return ServerError::MaximumPayloadViolation,
+            "invalid subject" => return ServerError::InvalidSubject,
+            _ => {}
+        }
+
+        if let Some(rest) = message.strip_prefix(SUB_VIOLATION_PREFIX) {
+            return match rest.find(QUEUE_SEPARATOR) {
+                Some(idx) => ServerError::SubscriptionPermissionsViolation(
+                    unquote(&rest[..idx]),
+                    Some(unquote(&rest[idx + QUEUE_SEPARATOR.len()..])),
+                ),
+                None => ServerError::SubscriptionPermissionsViolation(unquote(rest), None),
+            };
+        }
+
+        if let Some(subject) = message.strip_prefix(PUB_VIOLATION_PREFIX) {
+            return ServerError::PublishPermissionsViolation(unquote(subject));
+        }
+
+        ServerError::Other(message.to_string())
+    }
+}
+
+/// nats-server 2.x quotes the subjects and queue groups it mentions, older versions don't
+fn unquote(s: &str) -> String {
+    s.trim_matches('"').to_string()
+}
+
 impl From<String> for ServerError {
     fn from(s: String) -> Self {
-        ServerError(s)
+        ServerError::from(s.as_str())
     }
 }
 
 impl fmt::Display for ServerError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_tuple("ServerError").field(&self.0).finish()
+        match self {
+            ServerError::UnknownProtocolOperation => write!(f, "'Unknown Protocol Operation'"),
+            ServerError::AttemptedToConnectToRoutePort => write!(f, "'Attempted To Connect To Route Port'"),
+            ServerError::AuthorizationViolation => write!(f, "'Authorization Violation'"),
+            ServerError::AuthenticationTimeout => write!(f, "'Authentication Timeout'"),
+            ServerError::InvalidClientProtocol => write!(f, "'Invalid Client Protocol'"),
+            ServerError::MaximumControlLineExceeded => write!(f, "'Maximum Control Line Exceeded'"),
+            ServerError::ParserError => write!(f, "'Parser Error'"),
+            ServerError::SecureConnectionTlsRequired => write!(f, "'Secure Connection - TLS Required'"),
+            ServerError::StaleConnection => write!(f, "'Stale Connection'"),
+            ServerError::MaximumConnectionsExceeded => write!(f, "'Maximum Connections Exceeded'"),
+            ServerError::SlowConsumer => write!(f, "'Slow Consumer'"),
+            ServerError::MaximumPayloadViolation => write!(f, "'Maximum Payload Violation'"),
+            ServerError::InvalidSubject => write!(f, "'Invalid Subject'"),
+            ServerError::SubscriptionPermissionsViolation(subject, None) => {
+                write!(f, "'{}\"{}\"'", SUB_VIOLATION_PREFIX, subject)
+            }
+            ServerError::SubscriptionPermissionsViolation(subject, Some(queue)) => {
+                write!(f, "'{}\"{}\"{}\"{}\"'", SUB_VIOLATION_PREFIX, subject, QUEUE_SEPARATOR, queue)
+            }
+            ServerError::PublishPermissionsViolation(subject) => {
+                write!(f, "'{}\"{}\"'", PUB_VIOLATION_PREFIX, subject)
+            }
+            ServerError::Other(message) => write!(f, "'{}'", message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ServerError;
+
+    #[test]
+    fn it_classifies() {
+        assert_eq!(ServerError::from("'Stale Connection'"), ServerError::StaleConnection);
+        assert_eq!(ServerError::from(" 'Authorization Timeout'"), ServerError::AuthenticationTimeout);
+        assert_eq!(
+            ServerError::from(r#"'Permissions Violation for Subscription to "foo.bar" using queue "baz"'"#),
+            ServerError::SubscriptionPermissionsViolation("foo.bar".into(), Some("baz".into()))
+        );
+        assert_eq!(
+            ServerError::from(r#"'Permissions Violation for Publish to "foo"'"#),
+            ServerError::PublishPermissionsViolation("foo".into())
+        );
+        assert_eq!(
+            ServerError::from("'Permissions Violation for Subscription to foo.bar'"),
+            ServerError::SubscriptionPermissionsViolation("foo.bar".into(), None)
+        );
+        assert_eq!(ServerError::from("'Something new'"), ServerError::Other("Something new".into()));
+    }
+
+    #[test]
+    fn it_knows_fatal_errors() {
+        assert!(ServerError::SlowConsumer.is_fatal());
+        assert!(ServerError::AuthorizationViolation.is_fatal());
+        assert!(!ServerError::InvalidSubject.is_fatal());
+        assert!(!ServerError::PublishPermissionsViolation("foo".into()).is_fatal());
+    }
+
+    #[test]
+    fn it_knows_errors_answering_commands() {
+        assert!(ServerError::InvalidSubject.replaces_ack());
+        assert!(ServerError::SubscriptionPermissionsViolation("foo".into(), None).replaces_ack());
+        assert!(!ServerError::PublishPermissionsViolation("foo".into()).replaces_ack());
+        assert!(!ServerError::StaleConnection.replaces_ack());
+        assert!(!ServerError::Other("Something new".into()).replaces_ack());
+    }
+
+    #[test]
+    fn it_roundtrips() {
+        let err = ServerError::SubscriptionPermissionsViolation("foo".into(), None);
+        assert_eq!(ServerError::from(err.to_string()), err);
+        let err = ServerError::SubscriptionPermissionsViolation("foo".into(), Some("bar".into()));
+        assert_eq!(ServerError::from(err.to_string()), err);
+        assert_eq!(ServerError::from(ServerError::SlowConsumer.to_string()), ServerError::SlowConsumer);
     }
 }
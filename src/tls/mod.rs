@@ -0,0 +1,105 @@
+use futures::prelude::*;
+use tokio_tcp::TcpStream;
+
+use error::NatsError;
+
+#[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+compile_error!("Either the `native-tls` or the `rustls` feature must be enabled");
+
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+mod native;
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+use self::native as backend;
+
+#[cfg(feature = "rustls")]
+mod rustls_backend;
+#[cfg(feature = "rustls")]
+use self::rustls_backend as backend;
+
+/// TLS stream of the backend selected through the cargo features. `rustls` takes precedence when both are enabled
+pub(crate) use self::backend::TlsStream;
+
+/// Certificate of a certificate authority trusted on top of the system ones
+#[derive(Debug, Clone, PartialEq)]
+pub enum TlsCertificate {
+    /// PEM-encoded certificate
+    Pem(Vec<u8>),
+    /// DER-encoded certificate
+    Der(Vec<u8>),
+}
+
+/// Identity presented by the client to servers verifying client certificates (`tls_verify`)
+#[derive(Clone, PartialEq)]
+pub enum TlsIdentity {
+    /// DER-encoded PKCS#12 archive holding the certificate chain and the private key, along with its password
+    Pkcs12 { der: Vec<u8>, password: String },
+    /// PEM-encoded certificate chain along with its PEM-encoded PKCS#8 private key
+    Pem { cert: Vec<u8>, key: Vec<u8> },
+}
+
+impl ::std::fmt::Debug for TlsIdentity {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            TlsIdentity::Pkcs12 { .. } => write!(f, "TlsIdentity::Pkcs12"),
+            TlsIdentity::Pem { .. } => write!(f, "TlsIdentity::Pem"),
+        }
+    }
+}
+
+/// Version of the TLS protocol
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TlsVersion {
+    Tlsv10,
+    Tlsv11,
+    Tlsv12,
+}
+
+/// TLS configuration used when connecting, and reconnecting, to servers requiring TLS. Everything is supported by
+/// the `native-tls` backend, while the `rustls` one doesn't support PKCS#12 identities nor versions prior to TLS 1.2
+#[derive(Debug, Default, Clone, PartialEq, Builder)]
+#[builder(default)]
+pub struct TlsConfig {
+    /// Certificate authorities trusted on top of the system ones, for servers using a private CA
+    pub root_certificates: Vec<TlsCertificate>,
+    /// Client certificate and key, for servers enforcing mutual TLS
+    pub identity: Option<TlsIdentity>,
+    /// Name checked against the certificate of the server instead of the host of its URL
+    #[builder(setter(into))]
+    pub server_name: Option<String>,
+    /// Minimum version of the protocol accepted, defaults to the one of the TLS implementation
+    pub min_protocol_version: Option<TlsVersion>,
+    /// Accepts any certificate and host name, only meant for local testing since it defeats the purpose of TLS
+    pub insecure: bool,
+}
+
+impl TlsConfig {
+    pub fn builder() -> TlsConfigBuilder {
+        TlsConfigBuilder::default()
+    }
+
+    /// Name to verify the certificate of the given host against
+    pub(crate) fn domain_for<'a>(&'a self, host: &'a str) -> &'a str {
+        self.server_name.as_ref().map_or(host, |name| name.as_str())
+    }
+
+    /// Upgrades an existing TCP socket to TLS, verifying the certificate of the server against the given host
+    pub(crate) fn upgrade(
+        &self,
+        host: &str,
+        socket: TcpStream,
+    ) -> impl Future<Item = TlsStream<TcpStream>, Error = NatsError> {
+        backend::upgrade(self, self.domain_for(host), socket)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TlsConfig;
+
+    #[test]
+    fn it_overrides_the_server_name() {
+        let config = TlsConfig::builder().server_name(Some("nats.internal".to_string())).build().unwrap();
+        assert_eq!(config.domain_for("127.0.0.1"), "nats.internal");
+        assert_eq!(TlsConfig::default().domain_for("127.0.0.1"), "127.0.0.1");
+    }
+}
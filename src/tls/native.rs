@@ -0,0 +1,92 @@
+use futures::{
+    future::{self, Either},
+    prelude::*,
+};
+use native_tls::{Certificate, Identity, Protocol, TlsConnector as NativeTlsConnector};
+use tokio_tcp::TcpStream;
+use tokio_tls::TlsConnector;
+
+use error::NatsError;
+
+use super::{TlsCertificate, TlsConfig, TlsIdentity, TlsVersion};
+
+pub(crate) use tokio_tls::TlsStream;
+
+/// Builds the `native-tls` connector matching the configuration
+fn connector(config: &TlsConfig) -> Result<TlsConnector, NatsError> {
+    let mut builder = NativeTlsConnector::builder();
+    for cert in &config.root_certificates {
+        builder.add_root_certificate(match cert {
+            TlsCertificate::Pem(pem) => Certificate::from_pem(pem)?,
+            TlsCertificate::Der(der) => Certificate::from_der(der)?,
+        });
+    }
+
+    if let Some(ref identity) = config.identity {
+        builder.identity(match identity {
+            TlsIdentity::Pkcs12 { der, password } => Identity::from_pkcs12(der, password)?,
+            TlsIdentity::Pem { cert, key } => Identity::from_pkcs8(cert, key)?,
+        });
+    }
+
+    if let Some(version) = config.min_protocol_version {
+        builder.min_protocol_version(Some(match version {
+            TlsVersion::Tlsv10 => Protocol::Tlsv10,
+            TlsVersion::Tlsv11 => Protocol::Tlsv11,
+            TlsVersion::Tlsv12 => Protocol::Tlsv12,
+        }));
+    }
+
+    if config.insecure {
+        warn!(target: "nitox", "TLS certificate verification is disabled");
+        builder.danger_accept_invalid_certs(true);
+        builder.danger_accept_invalid_hostnames(true);
+    }
+
+    Ok(builder.build()?.into())
+}
+
+pub(crate) fn upgrade(
+    config: &TlsConfig,
+    domain: &str,
+    socket: TcpStream,
+) -> impl Future<Item = TlsStream<TcpStream>, Error = NatsError> {
+    let tls_connector = match connector(config) {
+        Ok(tls_connector) => tls_connector,
+        Err(e) => return Either::A(future::err(e)),
+    };
+
+    debug!(target: "nitox", "Connecting to {} through TLS over TCP with native-tls", domain);
+    Either::B(tls_connector.connect(domain, socket).from_err())
+}
+
+impl From<::native_tls::Error> for NatsError {
+    fn from(err: ::native_tls::Error) -> Self {
+        NatsError::TlsError(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::connector;
+    use tls::{TlsCertificate, TlsConfig, TlsVersion};
+
+    #[test]
+    fn it_builds_connectors() {
+        let config = TlsConfig::builder()
+            .min_protocol_version(Some(TlsVersion::Tlsv12))
+            .insecure(true)
+            .build()
+            .unwrap();
+        assert!(connector(&config).is_ok());
+    }
+
+    #[test]
+    fn it_rejects_invalid_certificates() {
+        let config = TlsConfig::builder()
+            .root_certificates(vec![TlsCertificate::Pem(b"not a certificate".to_vec())])
+            .build()
+            .unwrap();
+        assert!(connector(&config).is_err());
+    }
+}
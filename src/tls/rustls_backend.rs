@@ -0,0 +1,198 @@
+use futures::{
+    future::{self, Either},
+    prelude::*,
+};
+use rustls::{
+    internal::pemfile, Certificate, ClientConfig, PrivateKey, ProtocolVersion, RootCertStore, ServerCertVerified,
+    ServerCertVerifier, TLSError,
+};
+use std::{io::BufReader, sync::Arc};
+use tokio_rustls::TlsConnector;
+use tokio_tcp::TcpStream;
+use webpki::DNSNameRef;
+use webpki_roots;
+
+use error::NatsError;
+
+use super::{TlsCertificate, TlsConfig, TlsIdentity, TlsVersion};
+
+pub(crate) use tokio_rustls::client::TlsStream;
+
+/// Name given to rustls in insecure mode when the host isn't a valid DNS name, an IP address for instance
+const INSECURE_SERVER_NAME: &str = "nats-server.invalid";
+
+/// Accepts any certificate, for the insecure mode
+struct NoVerification;
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        _presented_certs: &[Certificate],
+        _dns_name: DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+fn pem_certs(pem: &[u8]) -> Result<Vec<Certificate>, NatsError> {
+    match pemfile::certs(&mut BufReader::new(pem)) {
+        Ok(ref certs) if certs.is_empty() => Err(NatsError::TlsError("no certificate found in PEM".into())),
+        Ok(certs) => Ok(certs),
+        Err(_) => Err(NatsError::TlsError("invalid PEM certificate".into())),
+    }
+}
+
+fn pem_key(pem: &[u8]) -> Result<PrivateKey, NatsError> {
+    let mut keys = pemfile::pkcs8_private_keys(&mut BufReader::new(pem))
+        .map_err(|_| NatsError::TlsError("invalid PEM private key".into()))?;
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut BufReader::new(pem))
+            .map_err(|_| NatsError::TlsError("invalid PEM private key".into()))?;
+    }
+
+    keys.pop()
+        .ok_or_else(|| NatsError::TlsError("no private key found in PEM".into()))
+}
+
+/// Builds the `rustls` configuration matching the configuration
+fn client_config(config: &TlsConfig) -> Result<ClientConfig, NatsError> {
+    let mut client_config = ClientConfig::new();
+    client_config
+        .root_store
+        .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+
+    for cert in &config.root_certificates {
+        let certs = match cert {
+            TlsCertificate::Pem(pem) => pem_certs(pem)?,
+            TlsCertificate::Der(der) => vec![Certificate(der.clone())],
+        };
+
+        for cert in &certs {
+            client_config
+                .root_store
+                .add(cert)
+                .map_err(|e| NatsError::TlsError(format!("invalid root certificate: {:?}", e)))?;
+        }
+    }
+
+    match config.identity {
+        Some(TlsIdentity::Pem { ref cert, ref key }) => {
+            client_config.set_single_client_cert(pem_certs(cert)?, pem_key(key)?);
+        }
+        Some(TlsIdentity::Pkcs12 { .. }) => {
+            return Err(NatsError::TlsError(
+                "PKCS#12 identities aren't supported by rustls, use a PEM certificate and key".into(),
+            ))
+        }
+        None => {}
+    }
+
+    match config.min_protocol_version {
+        Some(TlsVersion::Tlsv12) => client_config.versions = vec![ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2],
+        // rustls doesn't support anything older than TLS 1.2 anyway
+        Some(TlsVersion::Tlsv10) | Some(TlsVersion::Tlsv11) | None => {}
+    }
+
+    if config.insecure {
+        warn!(target: "nitox", "TLS certificate verification is disabled");
+        client_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(NoVerification));
+    }
+
+    Ok(client_config)
+}
+
+pub(crate) fn upgrade(
+    config: &TlsConfig,
+    domain: &str,
+    socket: TcpStream,
+) -> impl Future<Item = TlsStream<TcpStream>, Error = NatsError> {
+    let res = client_config(config).and_then(|mut client_config| {
+        server_name(config, &mut client_config, domain).map(|domain| (client_config, domain))
+    });
+
+    let (client_config, dns_name) = match res {
+        Ok(res) => res,
+        Err(e) => return Either::A(future::err(e)),
+    };
+
+    debug!(target: "nitox", "Connecting to {} through TLS over TCP with rustls", domain);
+    let tls_connector = TlsConnector::from(Arc::new(client_config));
+    Either::B(tls_connector.connect(dns_name, socket).from_err())
+}
+
+/// Name the certificate of the server is checked against. webpki only accepts DNS names, which doesn't matter in
+/// insecure mode since nothing is checked: a placeholder is used instead, and not sent as SNI
+fn server_name<'a>(
+    config: &TlsConfig,
+    client_config: &mut ClientConfig,
+    domain: &'a str,
+) -> Result<DNSNameRef<'a>, NatsError> {
+    match DNSNameRef::try_from_ascii_str(domain) {
+        Ok(name) => Ok(name),
+        Err(_) if config.insecure => {
+            client_config.enable_sni = false;
+            DNSNameRef::try_from_ascii_str(INSECURE_SERVER_NAME).map_err(|_| invalid_server_name())
+        }
+        Err(_) => Err(invalid_server_name()),
+    }
+}
+
+fn invalid_server_name() -> NatsError {
+    NatsError::TlsError("the server name isn't a valid DNS name, set `TlsConfig::server_name`".into())
+}
+
+impl From<TLSError> for NatsError {
+    fn from(err: TLSError) -> Self {
+        NatsError::TlsError(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{client_config, server_name};
+    use tls::{TlsCertificate, TlsConfig, TlsIdentity, TlsVersion};
+
+    #[test]
+    fn it_builds_client_configs() {
+        let config = TlsConfig::builder()
+            .min_protocol_version(Some(TlsVersion::Tlsv12))
+            .insecure(true)
+            .build()
+            .unwrap();
+        assert!(client_config(&config).is_ok());
+    }
+
+    #[test]
+    fn it_accepts_ip_addresses_when_insecure() {
+        let config = TlsConfig::builder().build().unwrap();
+        let mut rustls_config = client_config(&config).unwrap();
+        assert!(server_name(&config, &mut rustls_config, "127.0.0.1").is_err());
+        assert!(server_name(&config, &mut rustls_config, "localhost").is_ok());
+
+        let config = TlsConfig::builder().insecure(true).build().unwrap();
+        let mut rustls_config = client_config(&config).unwrap();
+        assert!(server_name(&config, &mut rustls_config, "127.0.0.1").is_ok());
+        assert!(!rustls_config.enable_sni);
+    }
+
+    #[test]
+    fn it_rejects_invalid_certificates() {
+        let config = TlsConfig::builder()
+            .root_certificates(vec![TlsCertificate::Pem(b"not a certificate".to_vec())])
+            .build()
+            .unwrap();
+        assert!(client_config(&config).is_err());
+
+        let config = TlsConfig::builder()
+            .identity(Some(TlsIdentity::Pkcs12 {
+                der: vec![],
+                password: "".into(),
+            })).build()
+            .unwrap();
+        assert!(client_config(&config).is_err());
+    }
+}